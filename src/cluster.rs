@@ -0,0 +1,233 @@
+use redis::{Client, ConnectionAddr, ConnectionInfo, InfoDict};
+
+use crate::config::Config;
+use crate::data::Data;
+use crate::metadata::{get_metadata, MatchCounts, Metadata};
+use crate::output::raw::SharedRawWriter;
+use crate::sampling::{self, SampleMode};
+
+// A primary node of a Redis Cluster, along with what's needed to connect to it
+pub struct Node {
+    pub addr: String,
+    pub connection_info: ConnectionInfo,
+}
+
+impl Node {
+    pub fn connect(&self) -> redis::RedisResult<redis::Connection> {
+        Client::open(self.connection_info.clone())?.get_connection()
+    }
+}
+
+// Whether the Redis we're connected to is part of a cluster, according to the "cluster" section of
+// INFO: https://redis.io/commands/info
+pub fn is_cluster(conn: &mut redis::Connection) -> bool {
+    redis::cmd("INFO")
+        .arg("cluster")
+        .query::<InfoDict>(conn)
+        .ok()
+        .and_then(|info| info.get::<i64>("cluster_enabled"))
+        .unwrap_or(0)
+        == 1
+}
+
+// Discovers the cluster's primaries via CLUSTER NODES. Connections to each node reuse the
+// credentials, database, and TLS settings of the given URL; only the host and port change.
+pub fn get_primaries(url: &str, conn: &mut redis::Connection) -> Result<Vec<Node>, String> {
+    let client = Client::open(url).map_err(|e| e.to_string())?;
+    let base_info = client.get_connection_info();
+
+    let cluster_nodes: String = redis::cmd("CLUSTER")
+        .arg("NODES")
+        .query(conn)
+        .map_err(|e| format!("CLUSTER NODES failed: {}", e))?;
+
+    let primaries = parse_primaries(&cluster_nodes)
+        .into_iter()
+        .map(|(host, port)| {
+            // TLS addresses are cloned rather than rebuilt, so whatever TLS settings this version of
+            // the redis crate keeps in them carry over
+            let mut addr = base_info.addr.clone();
+            if let ConnectionAddr::TcpTls {
+                host: tls_host,
+                port: tls_port,
+                ..
+            } = &mut addr
+            {
+                *tls_host = host.clone();
+                *tls_port = port;
+            } else {
+                addr = ConnectionAddr::Tcp(host.clone(), port);
+            }
+
+            Node {
+                addr: format!("{}:{}", host, port),
+                connection_info: ConnectionInfo {
+                    addr,
+                    redis: base_info.redis.clone(),
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if primaries.is_empty() {
+        Err("No healthy primaries found in CLUSTER NODES".to_string())
+    } else {
+        Ok(primaries)
+    }
+}
+
+// Samples every primary in the cluster, one after another. With random sampling, the requested
+// number of samples (and --max-draws) is split between the primaries in proportion to how many keys
+// they hold, so that the merged sample is still representative of the whole cluster.
+pub fn collect_samples(
+    config: &Config,
+    conn: &mut redis::Connection,
+    raw: Option<SharedRawWriter>,
+) -> Result<Vec<(String, Metadata, Data)>, String> {
+    let primaries = get_primaries(&config.url, conn)?;
+
+    let mut nodes = Vec::with_capacity(primaries.len());
+    for node in primaries.iter() {
+        let mut node_conn = node
+            .connect()
+            .map_err(|e| format!("Connecting to {} failed: {}", node.addr, e))?;
+        let node_metadata = get_metadata(&mut node_conn);
+        nodes.push((node, node_conn, node_metadata));
+    }
+
+    let key_counts: Vec<u64> = nodes.iter().map(|(_, _, m)| m.total_keys).collect();
+    let n_samples = allocate_samples(config.n_samples, &key_counts);
+    let max_draws = allocate_samples(config.max_draws, &key_counts);
+
+    let mut results = Vec::with_capacity(nodes.len());
+    for (idx, (node, mut node_conn, mut node_metadata)) in nodes.into_iter().enumerate() {
+        let node_config = Config {
+            n_samples: n_samples[idx],
+            max_draws: max_draws[idx],
+            ..config.clone()
+        };
+
+        let node_data = if node_config.sample_mode == SampleMode::Random && n_samples[idx] == 0 {
+            // Nothing was drawn from this node, so with --match or --exclude there's no telling how
+            // many of its keys match. It counts as none rather than all of them.
+            if config.filters_keys() {
+                node_metadata.match_counts = Some(MatchCounts {
+                    examined: Some(0),
+                    matched: 0,
+                });
+            }
+            Data::new(&node_config)
+        } else {
+            sampling::collect_samples(
+                &node_config,
+                &mut node_conn,
+                &mut node_metadata,
+                raw.clone(),
+            )
+        };
+
+        results.push((node.addr.clone(), node_metadata, node_data));
+    }
+
+    Ok(results)
+}
+
+// Each line of CLUSTER NODES looks like this (see https://redis.io/commands/cluster-nodes):
+//
+//     <id> <ip:port@cport[,hostname]> <flags> <master> <ping-sent> <pong-recv> <epoch> <link> ...
+//
+// We want the address of every node flagged as a "master" that isn't failing or missing an address.
+fn parse_primaries(cluster_nodes: &str) -> Vec<(String, u16)> {
+    let mut primaries = Vec::new();
+
+    for line in cluster_nodes.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            continue;
+        }
+
+        let flags: Vec<&str> = fields[2].split(',').collect();
+        let is_healthy_primary =
+            flags.contains(&"master") && !flags.contains(&"fail") && !flags.contains(&"noaddr");
+        if !is_healthy_primary {
+            continue;
+        }
+
+        // Strip the optional ",hostname" and "@cport" suffixes, leaving "ip:port"
+        let addr = fields[1].split(',').next().unwrap();
+        let addr = addr.split('@').next().unwrap();
+        if let Some((host, port)) = addr.rsplit_once(':') {
+            if let Ok(port) = port.parse() {
+                if !host.is_empty() {
+                    primaries.push((host.to_string(), port));
+                }
+            }
+        }
+    }
+
+    primaries
+}
+
+// Splits `n` samples between nodes in proportion to how many keys each one has. This uses the
+// largest remainder method, so the allocations always add up to exactly `n` (unless the cluster is
+// empty).
+pub fn allocate_samples(n: usize, key_counts: &[u64]) -> Vec<usize> {
+    let total_keys: u64 = key_counts.iter().sum();
+    if total_keys == 0 {
+        return vec![0; key_counts.len()];
+    }
+
+    let quotas: Vec<f64> = key_counts
+        .iter()
+        .map(|&count| n as f64 * count as f64 / total_keys as f64)
+        .collect();
+    let mut allocations: Vec<usize> = quotas.iter().map(|q| q.floor() as usize).collect();
+
+    // Hand out whatever's left over to the nodes with the largest fractional remainders
+    let mut by_remainder: Vec<usize> = (0..quotas.len()).collect();
+    by_remainder.sort_by(|&a, &b| {
+        let rem_a = quotas[a] - quotas[a].floor();
+        let rem_b = quotas[b] - quotas[b].floor();
+        rem_b.partial_cmp(&rem_a).unwrap()
+    });
+    let leftover = n - allocations.iter().sum::<usize>();
+    for &idx in by_remainder.iter().take(leftover) {
+        allocations[idx] += 1;
+    }
+
+    allocations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_primaries_works() {
+        let cluster_nodes = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004,host4 slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:30002@31002 master - 0 1426238316232 2 connected 5461-10922
+292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f 127.0.0.1:30003@31003 master - 0 1426238318243 3 connected 10923-16383
+6ec23923021cf3ffec47632106199cb7f496ce01 127.0.0.1:30005@31005 slave 67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 0 1426238316232 5 connected
+824fe116063bc5fcf9f4ffd895bc17aee7731ac3 127.0.0.1:30006@31006 master,fail - 1426238316232 0 6 disconnected
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@31001,host1 myself,master - 0 0 1 connected 0-5460
+";
+
+        assert_eq!(
+            parse_primaries(cluster_nodes),
+            vec![
+                ("127.0.0.1".to_string(), 30002),
+                ("127.0.0.1".to_string(), 30003),
+                ("127.0.0.1".to_string(), 30001),
+            ]
+        );
+    }
+
+    #[test]
+    fn allocate_samples_works() {
+        assert_eq!(allocate_samples(100, &[50, 30, 20]), vec![50, 30, 20]);
+        assert_eq!(allocate_samples(10, &[1, 1, 1]), vec![4, 3, 3]);
+        assert_eq!(allocate_samples(10, &[0, 5, 0]), vec![0, 10, 0]);
+        assert_eq!(allocate_samples(10, &[0, 0]), vec![0, 0]);
+    }
+}
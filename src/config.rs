@@ -1,17 +1,52 @@
 use std::collections::HashSet;
+use std::ffi::OsString;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::builder::NonEmptyStringValueParser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use rand::RngCore;
 
+use crate::data::escape::KeyEscape;
+use crate::data::pattern::{glob_matches, BinDef, Glob};
+use crate::data::redact::Redact;
+use crate::data::BinMode;
+use crate::output::raw::RawFormat;
 use crate::output::OutputMode;
-use crate::sampling::SampleMode;
+use crate::sampling::{SampleBackend, SampleMode};
 use crate::stats::Stats;
 
-#[derive(Parser, Eq, PartialEq, Debug)]
+pub mod profile;
+
+#[derive(Parser, Eq, PartialEq, Clone, Debug)]
 #[clap(version)]
 pub struct Config {
+    #[clap(
+        long = "config",
+        value_name = "FILE",
+        help = "Read options from a profile in FILE [default: ./redis-keyspace-stats.toml]"
+    )]
+    pub config_path: Option<String>,
+
+    #[clap(
+        long = "profile",
+        value_name = "NAME",
+        help = "The profile to read from the config file [default: default]"
+    )]
+    pub profile: Option<String>,
+
     #[clap(long = "sample", default_value = "random", value_enum, value_parser)]
     pub sample_mode: SampleMode,
 
+    #[clap(
+        long = "backend",
+        default_value = "pipeline",
+        value_enum,
+        value_parser,
+        help = "Collect random samples via pipelines or a Lua script"
+    )]
+    pub sample_backend: SampleBackend,
+
     #[clap(
         short = 'n',
         long = "samples",
@@ -20,12 +55,57 @@ pub struct Config {
     )]
     pub n_samples: usize,
 
+    #[clap(
+        long = "match",
+        help = "Only sample keys matching these glob-style patterns"
+    )]
+    pub match_patterns: Vec<Glob>,
+
+    #[clap(
+        long = "exclude",
+        help = "Don't sample keys matching these glob-style patterns at all"
+    )]
+    pub exclude_patterns: Vec<Glob>,
+
+    #[clap(
+        long = "max-draws",
+        default_value = "100000",
+        help = "Max random keys to try with --match, --exclude, or --target-error"
+    )]
+    pub max_draws: usize,
+
+    #[clap(
+        long = "target-error",
+        value_name = "PCT",
+        help = "Keep sampling until estimated totals are within ±PCT%"
+    )]
+    pub target_error_pct: Option<u64>,
+
     #[clap(long = "batch-size", default_value = "100")]
     pub batch_size: usize,
 
     #[clap(long = "batch-sleep-ms", default_value = "100")]
     pub batch_sleep_ms: u64,
 
+    #[clap(
+        long = "max-latency-ms",
+        help = "Adapt batches to keep round trips under this latency"
+    )]
+    pub max_latency_ms: Option<u64>,
+
+    #[clap(
+        long = "max-server-ops",
+        help = "Adapt batches to keep Redis under this many ops/sec"
+    )]
+    pub max_server_ops: Option<u64>,
+
+    #[clap(
+        long = "max-server-cpu",
+        value_name = "PCT",
+        help = "Adapt batches to keep Redis under this CPU usage"
+    )]
+    pub max_server_cpu_pct: Option<u64>,
+
     #[clap(
         long = "stats",
         use_value_delimiter = true,
@@ -35,6 +115,22 @@ pub struct Config {
     )]
     pub stats: Vec<Stats>,
 
+    #[clap(
+        long = "idle-threshold",
+        default_value = "7days",
+        value_parser = humantime::parse_duration,
+        help = "Report the % of keys idle for longer than this"
+    )]
+    pub idle_threshold: Duration,
+
+    #[clap(
+        long = "top",
+        value_name = "N",
+        default_value = "0",
+        help = "Also list the N largest keys in each bin"
+    )]
+    pub top: usize,
+
     #[clap(
         short = 'o',
         long = "out",
@@ -44,14 +140,145 @@ pub struct Config {
     )]
     pub output_mode: OutputMode,
 
+    #[clap(
+        long = "delimiter",
+        default_value = ":",
+        help = "For -o tree, split keys into prefixes on this character"
+    )]
+    pub delimiter: char,
+
+    #[clap(
+        long = "tree-depth",
+        value_name = "N",
+        default_value = "3",
+        help = "For -o tree, split keys into at most N levels of prefixes"
+    )]
+    pub tree_depth: usize,
+
+    #[clap(
+        long = "tree-min-pct",
+        value_name = "PCT",
+        default_value = "1",
+        help = "For -o tree, hide prefixes with under PCT% of both keys and memory"
+    )]
+    pub tree_min_pct: u64,
+
+    #[clap(
+        long = "raw",
+        value_name = "FILE",
+        help = "Also write every sampled key to FILE (\"-\" for STDOUT, with -o none)"
+    )]
+    pub raw_path: Option<String>,
+
+    #[clap(long = "raw-format", default_value = "csv", value_enum, value_parser)]
+    pub raw_format: RawFormat,
+
+    #[clap(
+        long = "key-escape",
+        default_value = "hex",
+        value_enum,
+        value_parser,
+        help = "How to show keys that aren't valid UTF-8 or have control characters"
+    )]
+    pub key_escape: KeyEscape,
+
+    #[clap(
+        long = "redact",
+        value_enum,
+        value_parser,
+        help = "Hide the parts of keys matched by wildcards in example keys, top keys, and --raw"
+    )]
+    pub redact: Option<Redact>,
+
+    // Doesn't require --redact, so the secret can stay in the environment for runs that don't redact
+    #[clap(
+        long = "redact-key",
+        value_name = "SECRET",
+        env = "REDIS_KEYSPACE_STATS_REDACT_KEY",
+        hide_env_values = true,
+        help = "Secret for --redact=hash, so hashes match across runs (random by default)"
+    )]
+    pub redact_key: Option<String>,
+
+    #[clap(long = "no-example-keys", help = "Don't show example keys")]
+    pub no_example_keys: bool,
+
     #[clap(long = "url", default_value = "redis://127.0.0.1", value_parser = parse_url)]
     pub url: String,
 
-    #[clap(help = "Glob-style patterns to group keys together")]
-    pub patterns: Vec<glob::Pattern>,
+    #[clap(
+        long = "per-node",
+        help = "For a Redis Cluster, also show stats for each primary"
+    )]
+    pub per_node: bool,
+
+    #[clap(
+        long = "auto-patterns",
+        help = "Discover patterns from random keys, in addition to PATTERNS"
+    )]
+    pub auto_patterns: bool,
+
+    #[clap(
+        long = "bin-mode",
+        default_value = "first-match",
+        value_enum,
+        value_parser,
+        help = "Put each key in the first bin it matches, or in every one"
+    )]
+    pub bin_mode: BinMode,
+
+    #[clap(
+        long = "label",
+        value_name = "LABEL=PATTERN",
+        value_parser = parse_label,
+        help = "Show the bin for one of the PATTERNS as LABEL in the table and HTML outputs"
+    )]
+    pub labels: Vec<(String, String)>,
+
+    #[clap(help = "Glob-style (or \"re:\" regex) patterns to group keys together")]
+    pub patterns: Vec<BinDef>,
 }
 
 impl Config {
+    // Parses the CLI args, filling in any options they don't give from a profile (see profile.rs)
+    pub fn load() -> Config {
+        // Args that aren't valid UTF-8 are left for clap to report
+        let args: Vec<OsString> = std::env::args_os().collect();
+
+        // A first pass finds the profile, and which options were given on the command line. It
+        // skips validation, since the profile might fill in what's missing, and connecting to
+        // --url.
+        let command = Config::command()
+            .ignore_errors(true)
+            .mut_arg("url", |a| a.value_parser(NonEmptyStringValueParser::new()));
+        let matches = command.clone().get_matches_from(&args);
+        let profile = profile::load(
+            matches.get_one::<String>("config_path").map(String::as_str),
+            matches.get_one::<String>("profile").map(String::as_str),
+        )
+        .and_then(|p| {
+            p.map(|p| profile::to_args(&p, &command, &matches))
+                .transpose()
+        })
+        .unwrap_or_else(|e| Config::command().error(ErrorKind::InvalidValue, e).exit());
+
+        let profile = match profile {
+            Some(profile) => profile,
+            None => return Config::parse_from(args),
+        };
+        let mut config = Config::parse_from(
+            args.iter()
+                .take(1)
+                .cloned()
+                .chain(profile.args.into_iter().map(OsString::from))
+                .chain(args.iter().skip(1).cloned()),
+        );
+        if !profile.patterns.is_empty() {
+            config.patterns = profile.patterns;
+        }
+        config
+    }
+
     // This is kind of a catch-all method for doing some additional munging of the Config struct
     // that doesn't nicely fit into what Clap gives us.
     pub fn normalize(&mut self) {
@@ -60,14 +287,113 @@ impl Config {
         self.stats.retain(|s| unique_stats.insert(s.clone()));
 
         // Append a "*" pattern, then deduplicate
-        self.patterns.push(glob::Pattern::new("*").unwrap());
+        self.patterns.push("*".parse().unwrap());
         let mut unique_patterns = HashSet::new();
-        self.patterns.retain(|s| unique_patterns.insert(s.clone()));
+        self.patterns
+            .retain(|def| unique_patterns.insert(def.pattern.as_str()));
+
+        // Labels can be given for any pattern, including the final "*"
+        for (label, pattern) in &self.labels {
+            match self
+                .patterns
+                .iter_mut()
+                .find(|def| def.pattern.as_str() == *pattern)
+            {
+                Some(def) => def.label = Some(label.clone()),
+                None => Config::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        format!(
+                            "--label is for \"{}\", which isn't one of the PATTERNS",
+                            pattern
+                        ),
+                    )
+                    .exit(),
+            }
+        }
+
+        // Every node of a cluster needs the same secret, so the same values hash the same
+        if self.redact.is_some() && self.redact_key.is_none() {
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            self.redact_key = Some(secret.iter().map(|b| format!("{:02x}", b)).collect());
+        }
+    }
+
+    // Adds patterns after the ones given on the command line, but before the final "*" (so this
+    // must be called after normalize)
+    pub fn add_patterns(&mut self, patterns: Vec<BinDef>) {
+        let catch_all = self.patterns.pop();
+        self.patterns.extend(patterns);
+        self.patterns.extend(catch_all);
+
+        let mut unique_patterns = HashSet::new();
+        self.patterns
+            .retain(|def| unique_patterns.insert(def.pattern.as_str()));
+    }
+
+    // Whether any patterns have labels, in which case machine-readable outputs include them
+    pub fn has_labels(&self) -> bool {
+        self.patterns.iter().any(|def| def.label.is_some())
+    }
+
+    // Whether any patterns have named captures, in which case machine-readable outputs include each
+    // bin's name alongside its pattern
+    pub fn has_named_captures(&self) -> bool {
+        self.patterns
+            .iter()
+            .any(|def| def.pattern.has_named_captures())
+    }
+
+    // Redis only tracks one of idle time or access frequency for each key, depending on whether the
+    // maxmemory-policy is an LFU one. If the one that isn't tracked was requested, this swaps it
+    // for the one that is. https://redis.io/docs/reference/eviction/
+    pub fn choose_access_stat(&mut self, maxmemory_policy: &str) {
+        let (untracked, tracked) = if maxmemory_policy.contains("lfu") {
+            ((Stats::Idle, "idle"), (Stats::Freq, "freq"))
+        } else {
+            ((Stats::Freq, "freq"), (Stats::Idle, "idle"))
+        };
+
+        if self.has_stat(&untracked.0) {
+            eprintln!(
+                "The maxmemory-policy is {}, so using the {} stat instead of {}",
+                maxmemory_policy, tracked.1, untracked.1
+            );
+            for stat in self.stats.iter_mut().filter(|s| **s == untracked.0) {
+                *stat = tracked.0.clone();
+            }
+
+            let mut unique_stats = HashSet::new();
+            self.stats.retain(|s| unique_stats.insert(s.clone()));
+        }
     }
 
     pub fn has_stat(&self, stat: &Stats) -> bool {
         self.stats.iter().any(|s| s == stat)
     }
+
+    // Whether the given key should be sampled at all, according to the --match and --exclude
+    // patterns
+    pub fn is_match(&self, key: &[u8]) -> bool {
+        let matches = |p: &Glob| glob_matches(p, key);
+        (self.match_patterns.is_empty() || self.match_patterns.iter().any(matches))
+            && !self.exclude_patterns.iter().any(matches)
+    }
+
+    // Whether only some keys are sampled (see is_match)
+    pub fn filters_keys(&self) -> bool {
+        !self.match_patterns.is_empty() || !self.exclude_patterns.is_empty()
+    }
+}
+
+// Splits a --label into the label and the pattern it's for. The label ends at the first "=", so the
+// pattern can have "=" in it.
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((label, pattern)) if !label.is_empty() => Ok((label.to_string(), pattern.to_string())),
+        _ => Err("must be LABEL=PATTERN".to_string()),
+    }
 }
 
 // Connects to the given Redis instance and executes a PING command. Returns whatever error message
@@ -76,7 +402,7 @@ fn parse_url(url: &str) -> Result<String, String> {
     let client = redis::Client::open(url).map_err(|e| e.to_string())?;
     let mut conn = client.get_connection().map_err(|e| e.to_string())?;
     redis::cmd("PING")
-        .query(&mut conn)
+        .query::<()>(&mut conn)
         .map_err(|e| e.to_string())?;
 
     Ok(url.to_string())
@@ -91,20 +417,122 @@ mod tests {
     #[test]
     fn config_parse_url() {
         assert_eq!(
-            Config::parse_from(&["test"]),
+            Config::parse_from(["test"]),
             Config {
+                config_path: None,
+                profile: None,
                 sample_mode: SampleMode::Random,
+                sample_backend: SampleBackend::Pipeline,
                 n_samples: 100,
+                match_patterns: vec![],
+                exclude_patterns: vec![],
+                max_draws: 100000,
+                target_error_pct: None,
                 batch_size: 100,
                 batch_sleep_ms: 100,
+                max_latency_ms: None,
+                max_server_ops: None,
+                max_server_cpu_pct: None,
                 stats: vec![Stats::Memory, Stats::Ttl],
+                idle_threshold: Duration::from_secs(7 * 24 * 60 * 60),
+                top: 0,
                 output_mode: OutputMode::Table,
+                delimiter: ':',
+                tree_depth: 3,
+                tree_min_pct: 1,
+                raw_path: None,
+                raw_format: RawFormat::Csv,
+                key_escape: KeyEscape::Hex,
+                redact: None,
+                redact_key: None,
+                no_example_keys: false,
                 url: "redis://127.0.0.1".to_string(),
+                per_node: false,
+                auto_patterns: false,
+                bin_mode: BinMode::FirstMatch,
+                labels: vec![],
                 patterns: vec![],
             }
         );
     }
 
+    #[test]
+    fn labels_work() {
+        let mut config = Config::parse_from([
+            "test",
+            "--label",
+            "Sessions=session:id=*",
+            "--label",
+            "Everything else=*",
+            "session:id=*",
+            "user:*",
+        ]);
+        config.normalize();
+        let labels: Vec<(Option<&str>, String)> = config
+            .patterns
+            .iter()
+            .map(|def| (def.label.as_deref(), def.pattern.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                (Some("Sessions"), "session:id=*".to_string()),
+                (None, "user:*".to_string()),
+                (Some("Everything else"), "*".to_string()),
+            ]
+        );
+        assert!(config.has_labels());
+
+        for bad in ["=x", "x"] {
+            assert!(Config::try_parse_from(["test", "--label", bad, "x"]).is_err());
+        }
+    }
+
+    #[test]
+    fn choose_access_stat_works() {
+        let mut config = Config::parse_from(["test", "--stats", "memory,idle,freq"]);
+        config.choose_access_stat("allkeys-lru");
+        assert_eq!(config.stats, vec![Stats::Memory, Stats::Idle]);
+
+        let mut config = Config::parse_from(["test", "--stats", "idle,memory"]);
+        config.choose_access_stat("volatile-lfu");
+        assert_eq!(config.stats, vec![Stats::Freq, Stats::Memory]);
+
+        let mut config = Config::parse_from(["test", "--stats", "memory"]);
+        config.choose_access_stat("noeviction");
+        assert_eq!(config.stats, vec![Stats::Memory]);
+    }
+
+    #[test]
+    fn is_match_works() {
+        let config = Config::parse_from(["test", "--match", "user:*", "--exclude", "*#memes"]);
+        assert!(config.filters_keys());
+        assert!(config.is_match(b"user:1#friends"));
+        assert!(!config.is_match(b"user:1#memes"));
+        assert!(!config.is_match(b"company:1#friends"));
+
+        let config = Config::parse_from(["test", "--exclude", "__lock:*"]);
+        assert!(config.is_match(b"user:1#friends"));
+        assert!(!config.is_match(b"__lock:abc"));
+        assert!(config.is_match(b"\xff:1"));
+    }
+
+    #[test]
+    fn add_patterns_works() {
+        let mut config = Config::parse_from(["test", "--auto-patterns", "user:*"]);
+        config.normalize();
+        config.add_patterns(vec![
+            "company:*".parse().unwrap(),
+            "user:*".parse().unwrap(),
+        ]);
+        let patterns: Vec<String> = config
+            .patterns
+            .iter()
+            .map(|def| def.pattern.as_str())
+            .collect();
+        assert_eq!(patterns, vec!["user:*", "company:*", "*"]);
+    }
+
     #[test]
     fn verify_clap() {
         Config::command().debug_assert();
@@ -0,0 +1,297 @@
+use std::io::ErrorKind;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use toml::{Table, Value};
+
+use crate::data::pattern::BinDef;
+
+// The config file that's read when --config isn't given, if it exists
+pub const DEFAULT_PATH: &str = "redis-keyspace-stats.toml";
+
+// The profile that's used when --profile isn't given, if the config file has one
+pub const DEFAULT_PROFILE: &str = "default";
+
+// Options that only make sense on the command line
+const CLI_ONLY: [&str; 4] = ["help", "version", "config", "profile"];
+
+// A named set of options from a config file like:
+//
+//   [profiles.prod-cache]
+//   url = "rediss://prod-cache:6379"
+//   batch-size = 50
+//   stats = ["memory", "ttl"]
+//   patterns = [
+//     "user:*",
+//     { label = "Sessions", pattern = "session:*" },
+//   ]
+//
+// Keys are the names of long options, and values are anything they'd take on the command line
+// ("true" for flags, and arrays for options that can be given more than once). Options are turned
+// into CLI args, so they're validated exactly like the CLI is, and anything given on the command
+// line takes precedence. "patterns" are the positional PATTERNS, either as they'd be given on the
+// command line, or as tables with a "pattern" and an optional "label".
+#[derive(Debug, Default, PartialEq)]
+pub struct Profile {
+    // Like "--batch-size=50"
+    pub args: Vec<String>,
+    pub patterns: Vec<BinDef>,
+}
+
+// Reads the given profile (or the default one) from the given config file (or the default one).
+// It's fine for the default file or profile to not exist, but not for ones that were asked for.
+pub fn load(path: Option<&str>, name: Option<&str>) -> Result<Option<Table>, String> {
+    load_or_default(path, name, DEFAULT_PATH)
+}
+
+fn load_or_default(
+    path: Option<&str>,
+    name: Option<&str>,
+    default_path: &str,
+) -> Result<Option<Table>, String> {
+    let is_default = path.is_none() && name.is_none();
+    let has_name = name.is_some();
+    let path = path.unwrap_or(default_path);
+    let name = name.unwrap_or(DEFAULT_PROFILE);
+
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if is_default && e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Can't read {}: {}", path, e)),
+    };
+
+    let mut file: Table = contents
+        .parse()
+        .map_err(|e| format!("Can't parse {}: {}", path, e))?;
+    let profiles = match file.remove("profiles") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err(format!("\"profiles\" in {} must be a table", path)),
+        None => Table::new(),
+    };
+    if let Some(key) = file.keys().next() {
+        return Err(format!(
+            "Unknown key \"{}\" in {} (options go in [profiles.NAME])",
+            key, path
+        ));
+    }
+
+    match profiles.get(name) {
+        Some(Value::Table(profile)) => Ok(Some(profile.clone())),
+        Some(_) => Err(format!("Profile \"{}\" in {} must be a table", name, path)),
+        None if !has_name => Ok(None),
+        None => {
+            let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            Err(format!(
+                "No profile \"{}\" in {} (it has: {})",
+                name,
+                path,
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+// Turns a profile's options into CLI args for the given command, skipping any that were already
+// given on the command line (according to the given matches)
+pub fn to_args(
+    profile: &Table,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Profile, String> {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let mut result = Profile::default();
+
+    for (key, value) in profile {
+        if key == "patterns" {
+            if !given("patterns") {
+                result.patterns = patterns(value)?;
+            }
+            continue;
+        }
+
+        let arg = command
+            .get_arguments()
+            .find(|a| a.get_long() == Some(key) && !CLI_ONLY.contains(&key.as_str()))
+            .ok_or_else(|| format!("Unknown option \"{}\" in profile", key))?;
+        if given(arg.get_id().as_str()) {
+            continue;
+        }
+
+        if arg.get_action().takes_values() {
+            for value in values(key, value)? {
+                result.args.push(format!("--{}={}", key, value));
+            }
+        } else {
+            match value {
+                Value::Boolean(true) => result.args.push(format!("--{}", key)),
+                Value::Boolean(false) => {}
+                _ => return Err(format!("\"{}\" in profile must be true or false", key)),
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+// A string or number (or an array of them) as CLI values
+fn values(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    let value_to_string = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        _ => Err(format!(
+            "\"{}\" in profile must be a string, a number, or an array of them",
+            key
+        )),
+    };
+
+    match value {
+        Value::Array(values) => values.iter().map(value_to_string).collect(),
+        value => Ok(vec![value_to_string(value)?]),
+    }
+}
+
+fn patterns(value: &Value) -> Result<Vec<BinDef>, String> {
+    let invalid = |e: String| format!("Invalid pattern in profile: {}", e);
+    let values = match value {
+        Value::Array(values) => values.clone(),
+        value => vec![value.clone()],
+    };
+
+    values
+        .iter()
+        .map(|value| match value {
+            Value::String(def) => def.parse().map_err(invalid),
+            Value::Table(def) => {
+                let label = match def.get("label") {
+                    Some(Value::String(label)) => Some(label.clone()),
+                    None => None,
+                    Some(_) => return Err("Pattern labels in profile must be strings".to_string()),
+                };
+                let pattern = match def.get("pattern") {
+                    Some(Value::String(pattern)) => pattern.parse().map_err(invalid)?,
+                    _ => return Err("Pattern tables in profile need a \"pattern\"".to_string()),
+                };
+                if let Some(key) = def.keys().find(|k| *k != "label" && *k != "pattern") {
+                    return Err(format!(
+                        "Unknown key \"{}\" in pattern table in profile",
+                        key
+                    ));
+                }
+                Ok(BinDef { label, pattern })
+            }
+            _ => Err("\"patterns\" in profile must be strings or tables".to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn profile_works() {
+        let dir = std::env::temp_dir().join(format!(
+            "redis-keyspace-stats-profile-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("profiles.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [profiles.default]
+            top = 5
+
+            [profiles.prod-cache]
+            url = "redis://prod-cache"
+            batch-size = 50
+            stats = ["memory", "type"]
+            per-node = true
+            auto-patterns = false
+            patterns = [
+                "user:*",
+                "type=web:*",
+                { label = "Odd = label", pattern = "company:*" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let default = load(Some(path), None).unwrap().unwrap();
+        assert_eq!(default["top"].as_integer(), Some(5));
+        let profile = load(Some(path), Some("prod-cache")).unwrap().unwrap();
+        assert!(load(Some(path), Some("nope"))
+            .unwrap_err()
+            .contains("it has: default, prod-cache"));
+
+        // The default file doesn't have to exist, but it's read when it does
+        let default_path = dir.join(DEFAULT_PATH);
+        let default_path = default_path.to_str().unwrap();
+        assert_eq!(load_or_default(None, None, default_path), Ok(None));
+        assert!(load_or_default(None, Some("prod-cache"), default_path).is_err());
+        std::fs::copy(path, default_path).unwrap();
+        assert_eq!(
+            load_or_default(None, None, default_path),
+            Ok(Some(default.clone()))
+        );
+
+        // Options given on the command line take precedence
+        let command = Config::command();
+        let matches = Config::command().ignore_errors(true).get_matches_from([
+            "test",
+            "--batch-size",
+            "10",
+            "company:*",
+        ]);
+        assert_eq!(
+            to_args(&profile, &command, &matches).unwrap(),
+            Profile {
+                args: vec![
+                    "--per-node".to_string(),
+                    "--stats=memory".to_string(),
+                    "--stats=type".to_string(),
+                    "--url=redis://prod-cache".to_string(),
+                ],
+                patterns: vec![],
+            }
+        );
+
+        let matches = Config::command()
+            .ignore_errors(true)
+            .get_matches_from(["test"]);
+        let args = to_args(&profile, &command, &matches).unwrap();
+        assert!(args.args.contains(&"--batch-size=50".to_string()));
+        let labels: Vec<(Option<&str>, String)> = args
+            .patterns
+            .iter()
+            .map(|def| (def.label.as_deref(), def.pattern.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                (None, "user:*".to_string()),
+                (None, "type=web:*".to_string()),
+                (Some("Odd = label"), "company:*".to_string()),
+            ]
+        );
+
+        for bad in [
+            "nope = 1",
+            "config = \"x\"",
+            "per-node = 1",
+            "top = true",
+            "patterns = [\"re:(\"]",
+            "patterns = [{ label = \"No pattern\" }]",
+        ] {
+            let profile: Table = bad.parse().unwrap();
+            assert!(to_args(&profile, &command, &matches).is_err(), "{}", bad);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
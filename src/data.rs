@@ -1,69 +1,360 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashSet};
 
 use crate::config::Config;
+use crate::data::bin::Bin;
+use crate::data::escape::KeyEscape;
+use crate::data::pattern::BinDef;
+use crate::data::redact::Redactor;
+use crate::data::tree::PrefixTree;
+use crate::output::raw::SharedRawWriter;
+use crate::output::OutputMode;
 use crate::sampling::sample::Sample;
+use crate::sampling::SampleMode;
 
+pub mod bin;
+pub mod count;
+pub mod encodings;
+pub mod escape;
+pub mod freq;
+pub mod idle;
+pub mod ids;
 pub mod keys;
 pub mod math;
 pub mod memory;
 pub mod other;
+pub mod pattern;
+pub mod redact;
+pub mod sketch;
+pub mod top;
+pub mod tree;
 pub mod ttl;
 pub mod types;
 
-pub type Key = String;
-pub type Samples = HashMap<Key, Sample>;
-pub type KeyPatterns = Vec<glob::Pattern>;
+// Keys are binary-safe, so they're kept as bytes (see escape.rs for how they're shown)
+pub type Key = Vec<u8>;
+pub type BinDefs = Vec<BinDef>;
 pub type Keys = Vec<Key>;
-pub type KeyBins = HashMap<glob::Pattern, Keys>;
+pub type BinName = String;
+pub type KeyBins = BTreeMap<BinKey, Bin>;
 
+// How many bins a pattern with named captures can have before keys with new capture values are
+// lumped together, so a capture that's unique per key can't make the number of bins grow with the
+// number of keys sampled (like MAX_CHILDREN in tree.rs)
+const MAX_BINS_PER_PATTERN: usize = 100;
+
+// The bin name that keys past MAX_BINS_PER_PATTERN are lumped into
+pub const OTHER_BIN: &str = "(other)";
+
+// Identifies a bin: the index of its pattern in the BinDefs, its name (the pattern itself, or the
+// values of its named captures), and its label (if its pattern has one). Bins are ordered by the
+// index of their pattern, then by name (for patterns with named captures, which can have more than
+// one bin).
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Debug)]
+pub struct BinKey {
+    pub idx: usize,
+    pub name: BinName,
+    pub label: Option<String>,
+}
+
+impl BinKey {
+    fn new(idx: usize, def: &BinDef, name: BinName) -> BinKey {
+        BinKey {
+            idx,
+            label: def.bin_label(&name),
+            name,
+        }
+    }
+
+    // What the table and HTML outputs show for the bin; machine-readable outputs show the name
+    pub fn display_name(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+}
+
+// Whether a key only counts toward the first pattern it matches, or toward every one (so bins can
+// overlap, and the "*" bin has every key)
+#[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
+pub enum BinMode {
+    FirstMatch,
+    AllMatches,
+}
+
+// Samples aren't kept around once they're added: each one is folded into the stats of its bin, so
+// even scanning a huge keyspace uses a flat amount of memory.
 #[derive(Debug)]
 pub struct Data {
-    samples: Samples,
-    patterns: KeyPatterns,
+    sample_count: usize,
+    // The keys sampled so far, which random sampling needs to skip repeats. A scan visits each key
+    // once anyways (see sample_all.rs), so these aren't kept when scanning.
+    sampled_keys: Option<HashSet<Key>>,
+    patterns: BinDefs,
     bins: KeyBins,
+    bin_mode: BinMode,
+    key_escape: KeyEscape,
+    // Only kept with --redact
+    redactor: Option<Redactor>,
+    n_top: usize,
+    // Only kept for -o tree
+    tree: Option<PrefixTree>,
+    raw: Option<SharedRawWriter>,
 }
 
 impl Data {
     pub fn new(config: &Config) -> Data {
-        let samples = HashMap::with_capacity(config.n_samples);
+        let sampled_keys = match config.sample_mode {
+            SampleMode::Random => Some(HashSet::with_capacity(config.n_samples)),
+            SampleMode::All => None,
+        };
         let patterns = config.patterns.clone();
-        let bins = patterns.iter().map(|p| (p.clone(), vec![])).collect();
+        // Bins for patterns with named captures are only created once a key matches
+        let bins = patterns
+            .iter()
+            .enumerate()
+            .filter(|(_idx, def)| !def.pattern.has_named_captures())
+            .map(|(idx, def)| {
+                let bin_key = BinKey::new(idx, def, def.pattern.as_str());
+                (bin_key, Bin::new(config.top))
+            })
+            .collect();
+        let tree = (config.output_mode == OutputMode::Tree).then(|| {
+            PrefixTree::new(
+                config.delimiter,
+                config.tree_depth,
+                config.key_escape.clone(),
+            )
+        });
 
         Data {
-            samples,
+            sample_count: 0,
+            sampled_keys,
             patterns,
             bins,
+            bin_mode: config.bin_mode.clone(),
+            key_escape: config.key_escape.clone(),
+            redactor: config
+                .redact
+                .clone()
+                .map(|mode| Redactor::new(mode, config.redact_key.as_deref().unwrap_or_default())),
+            n_top: config.top,
+            tree,
+            raw: None,
         }
     }
 
+    // Writes each sample to the given --raw writer as soon as it's added
+    pub fn stream_raw_to(&mut self, raw: SharedRawWriter) {
+        self.raw = Some(raw);
+    }
+
     pub fn sample_count(&self) -> usize {
-        self.samples.len()
+        self.sample_count
+    }
+
+    // With --bin-mode=all-matches, the sample is added to every bin it matches (and written to
+    // --raw once for each)
+    pub fn add_sample(&mut self, key: Key, sample: Sample) {
+        let mut bin_keys = self.patterns.iter().enumerate().filter_map(|(idx, def)| {
+            let name = def.pattern.bin_name(&key, &self.key_escape)?;
+            Some(BinKey::new(idx, def, name))
+        });
+        let bin_keys: Vec<BinKey> = match self.bin_mode {
+            BinMode::FirstMatch => bin_keys.next().into_iter().collect(),
+            BinMode::AllMatches => bin_keys.collect(),
+        };
+        if bin_keys.is_empty() {
+            panic!(
+                "Key: {} did not match any bins for some reason",
+                self.key_escape.escape(&key)
+            );
+        }
+
+        for bin_key in bin_keys {
+            let bin_key = self.capped(bin_key);
+
+            // With --redact, the key is redacted by the pattern of each bin it's added to, before
+            // it's kept as an example or top key, or written to --raw
+            let redacted = self
+                .redactor
+                .as_ref()
+                .map(|r| r.redact(&self.patterns[bin_key.idx].pattern, &key));
+            let shown_key = redacted.as_ref().unwrap_or(&key);
+
+            if let Some(raw) = &self.raw {
+                raw.borrow_mut()
+                    .write(
+                        &self.key_escape.escape(shown_key),
+                        &self.patterns[bin_key.idx].pattern.as_str(),
+                        &bin_key,
+                        &sample,
+                    )
+                    .expect("Writing --raw output failed");
+            }
+
+            let n_top = self.n_top;
+            self.bins
+                .entry(bin_key)
+                .or_insert_with(|| Bin::new(n_top))
+                .add(shown_key, &sample);
+        }
+
+        if let Some(tree) = &mut self.tree {
+            tree.add(&key, &sample);
+        }
+        self.sample_count += 1;
+        if let Some(sampled_keys) = &mut self.sampled_keys {
+            sampled_keys.insert(key);
+        }
     }
 
-    pub fn add_sample(&mut self, key: String, sample: Sample) {
-        self.samples.insert(key.clone(), sample);
+    // The given bin, or its pattern's OTHER_BIN if it's a new one and the pattern already has
+    // MAX_BINS_PER_PATTERN bins
+    fn capped(&self, bin_key: BinKey) -> BinKey {
+        let first = BinKey {
+            idx: bin_key.idx,
+            name: String::new(),
+            label: None,
+        };
+        let pattern_bins = self
+            .bins
+            .range(first..)
+            .take_while(|(other, _bin)| other.idx == bin_key.idx)
+            .count();
 
-        if let Some(pattern) = self.patterns.iter().find(|p| p.matches(&key)) {
-            let bin = self.bins.get_mut(pattern).unwrap();
-            bin.push(key.clone());
+        if pattern_bins < MAX_BINS_PER_PATTERN || self.bins.contains_key(&bin_key) {
+            bin_key
         } else {
-            panic!("Key: {} did not match any bins for some reason", key);
+            let idx = bin_key.idx;
+            BinKey::new(idx, &self.patterns[idx], OTHER_BIN.to_string())
         }
     }
 
-    pub fn patterns(&self) -> &KeyPatterns {
-        &self.patterns
+    // Moves all of the samples from another Data (which must have the same patterns) into this one
+    pub fn merge(&mut self, other: Data) {
+        for (bin_key, bin) in other.bins {
+            let bin_key = self.capped(bin_key);
+            match self.bins.get_mut(&bin_key) {
+                Some(self_bin) => self_bin.merge(bin),
+                None => {
+                    self.bins.insert(bin_key, bin);
+                }
+            }
+        }
+        if let (Some(tree), Some(other_tree)) = (&mut self.tree, other.tree) {
+            tree.merge(other_tree);
+        }
+        self.sample_count += other.sample_count;
+        if let (Some(sampled_keys), Some(other_keys)) = (&mut self.sampled_keys, other.sampled_keys)
+        {
+            sampled_keys.extend(other_keys);
+        }
     }
 
-    pub fn bins(&self) -> &KeyBins {
-        &self.bins
+    // Every bin, in the order they should be shown
+    pub fn bins(&self) -> impl Iterator<Item = (&BinKey, &Bin)> {
+        self.bins.iter()
     }
 
-    pub fn has_sample(&self, key: &String) -> bool {
-        self.samples.contains_key(key)
+    pub fn tree(&self) -> Option<&PrefixTree> {
+        self.tree.as_ref()
     }
 
-    pub fn get_sample(&self, key: &String) -> Option<&Sample> {
-        self.samples.get(key)
+    pub fn has_sample(&self, key: &Key) -> bool {
+        match &self.sampled_keys {
+            Some(sampled_keys) => sampled_keys.contains(key),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::sampling::sample::SampleValue::*;
+
+    #[test]
+    fn bin_modes_work() {
+        let sample = || Sample {
+            exists: Unsampled,
+            memory: Sampled(10),
+            ttl_ms: Unsampled,
+            expires_at: Unsampled,
+            type_: Unsampled,
+            count: Unsampled,
+            encoding: Unsampled,
+            idle: Unsampled,
+            freq: Unsampled,
+        };
+        let key_counts = |data: &Data| -> Vec<(String, u64)> {
+            data.bins()
+                .map(|(bin_key, bin)| (bin_key.display_name().to_string(), bin.key_count))
+                .collect()
+        };
+
+        for (bin_mode, expected) in [
+            (
+                "first-match",
+                vec![("*:sessions", 2), ("Tenant 42", 1), ("*", 1)],
+            ),
+            (
+                "all-matches",
+                vec![("*:sessions", 2), ("Tenant 42", 2), ("*", 4)],
+            ),
+        ] {
+            let mut config = Config::parse_from([
+                "test",
+                "--bin-mode",
+                bin_mode,
+                "--label",
+                "Tenant 42=tenant42:*",
+                "*:sessions",
+                "tenant42:*",
+            ]);
+            config.normalize();
+            let mut data = Data::new(&config);
+            for key in [
+                "tenant1:sessions",
+                "tenant42:sessions",
+                "tenant42:cache",
+                "lock",
+            ] {
+                data.add_sample(key.as_bytes().to_vec(), sample());
+            }
+
+            let expected: Vec<(String, u64)> = expected
+                .into_iter()
+                .map(|(name, count)| (name.to_string(), count))
+                .collect();
+            assert_eq!(key_counts(&data), expected);
+            assert_eq!(data.sample_count(), 4);
+        }
+    }
+
+    #[test]
+    fn named_capture_bins_are_capped() {
+        let sample = || Sample::new(&[], &Config::parse_from(["test"]));
+        let config = Config::parse_from(["test", "re:^user:(?P<id>[0-9]+)$"]);
+        let mut data = Data::new(&config);
+        for idx in 0..MAX_BINS_PER_PATTERN + 5 {
+            data.add_sample(format!("user:{}", idx).into_bytes(), sample());
+        }
+        data.add_sample(b"user:0".to_vec(), sample());
+        assert_eq!(data.bins().count(), MAX_BINS_PER_PATTERN + 1);
+
+        let key_count = |data: &Data, name: &str| {
+            data.bins()
+                .find(|(bin_key, _bin)| bin_key.name == name)
+                .map(|(_bin_key, bin)| bin.key_count)
+        };
+        assert_eq!(key_count(&data, "id=0"), Some(2));
+        assert_eq!(key_count(&data, OTHER_BIN), Some(5));
+
+        // Merging doesn't get around the cap either
+        let mut other = Data::new(&config);
+        other.add_sample(b"user:1000".to_vec(), sample());
+        data.merge(other);
+        assert_eq!(data.bins().count(), MAX_BINS_PER_PATTERN + 1);
+        assert_eq!(key_count(&data, OTHER_BIN), Some(6));
     }
 }
@@ -0,0 +1,246 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use rand::Rng;
+
+use crate::data::sketch::Sketch;
+use crate::data::{Key, Keys};
+use crate::sampling::sample::Sample;
+
+// How many example keys are kept for each bin
+const N_EXAMPLE_KEYS: usize = 5;
+
+// Everything we need to know about the keys that matched one pattern. Samples are folded in as they
+// arrive instead of being kept around, so memory use doesn't grow with the number of keys sampled:
+// stats are kept as counters, sums, and Sketches, and only a handful of keys themselves (examples,
+// and the largest keys for --top) are remembered.
+#[derive(Debug)]
+pub struct Bin {
+    pub key_count: u64,
+    // A uniformly random selection of the keys in this bin (a reservoir sample)
+    pub example_keys: Keys,
+    pub memory: Totals,
+    // The number of keys whose TTL was sampled, and the TTLs (in milliseconds) of those that have
+    // one
+    pub ttl_count: u64,
+    pub ttl_ms: Sketch,
+    pub expiry_range: Option<(SystemTime, SystemTime)>,
+    pub type_counts: HashMap<String, u64>,
+    // The number of keys with each encoding, for each type
+    pub encoding_counts: HashMap<String, HashMap<String, u64>>,
+    pub count: Totals,
+    pub idle: Sketch,
+    pub freq: Sketch,
+    // The largest keys (largest first), up to --top of them
+    pub top_by_memory: Vec<(Key, u64)>,
+    pub top_by_count: Vec<(Key, u64)>,
+    n_top: usize,
+}
+
+// Running totals of a stat, for estimating its total (and margin of error) and percentiles
+#[derive(Debug, Default)]
+pub struct Totals {
+    pub sum: u64,
+    pub sum_of_squares: f64,
+    pub values: Sketch,
+}
+
+impl Totals {
+    fn add(&mut self, value: u64) {
+        self.sum += value;
+        self.sum_of_squares += (value as f64).powi(2);
+        self.values.add(value as f64);
+    }
+
+    fn merge(&mut self, other: Totals) {
+        self.sum += other.sum;
+        self.sum_of_squares += other.sum_of_squares;
+        self.values.merge(other.values);
+    }
+}
+
+impl Bin {
+    pub fn new(n_top: usize) -> Bin {
+        Bin {
+            key_count: 0,
+            example_keys: vec![],
+            memory: Totals::default(),
+            ttl_count: 0,
+            ttl_ms: Sketch::default(),
+            expiry_range: None,
+            type_counts: HashMap::new(),
+            encoding_counts: HashMap::new(),
+            count: Totals::default(),
+            idle: Sketch::default(),
+            freq: Sketch::default(),
+            top_by_memory: vec![],
+            top_by_count: vec![],
+            n_top,
+        }
+    }
+
+    // Stats that weren't sampled are skipped
+    pub fn add(&mut self, key: &Key, sample: &Sample) {
+        self.key_count += 1;
+
+        // Algorithm R: https://en.wikipedia.org/wiki/Reservoir_sampling#Simple:_Algorithm_R
+        if self.example_keys.len() < N_EXAMPLE_KEYS {
+            self.example_keys.push(key.clone());
+        } else {
+            let idx = rand::thread_rng().gen_range(0..self.key_count) as usize;
+            if idx < N_EXAMPLE_KEYS {
+                self.example_keys[idx] = key.clone();
+            }
+        }
+
+        if let Some(&memory) = sample.memory.get() {
+            self.memory.add(memory);
+            add_top(&mut self.top_by_memory, self.n_top, key, memory);
+        }
+
+        if let Some(&ttl_ms) = sample.ttl_ms.get() {
+            self.ttl_count += 1;
+            if ttl_ms >= 0 {
+                self.ttl_ms.add(ttl_ms as f64);
+            }
+        }
+        if let Some(&expires_at) = sample.expires_at.get() {
+            self.expiry_range = Some(match self.expiry_range {
+                None => (expires_at, expires_at),
+                Some((earliest, latest)) => (earliest.min(expires_at), latest.max(expires_at)),
+            });
+        }
+
+        if let Some(type_) = sample.type_.get() {
+            *self.type_counts.entry(type_.clone()).or_insert(0) += 1;
+            if let Some(encoding) = sample.encoding.get() {
+                *self
+                    .encoding_counts
+                    .entry(type_.clone())
+                    .or_default()
+                    .entry(encoding.clone())
+                    .or_insert(0) += 1;
+            }
+        }
+
+        if let Some(&count) = sample.count.get() {
+            self.count.add(count);
+            add_top(&mut self.top_by_count, self.n_top, key, count);
+        }
+
+        if let Some(&idle) = sample.idle.get() {
+            self.idle.add(idle as f64);
+        }
+
+        if let Some(&freq) = sample.freq.get() {
+            self.freq.add(freq as f64);
+        }
+    }
+
+    // Moves all of the stats from another Bin into this one
+    pub fn merge(&mut self, other: Bin) {
+        // Pick examples from each bin in proportion to its size, so they stay a uniform selection
+        let mut rng = rand::thread_rng();
+        let total_count = self.key_count + other.key_count;
+        let mut self_examples = std::mem::take(&mut self.example_keys).into_iter();
+        let mut other_examples = other.example_keys.into_iter();
+        while self.example_keys.len() < N_EXAMPLE_KEYS && total_count > 0 {
+            let example = if rng.gen_range(0..total_count) < self.key_count {
+                self_examples.next().or_else(|| other_examples.next())
+            } else {
+                other_examples.next().or_else(|| self_examples.next())
+            };
+            match example {
+                Some(key) => self.example_keys.push(key),
+                None => break,
+            }
+        }
+        self.key_count += other.key_count;
+
+        self.memory.merge(other.memory);
+        self.ttl_count += other.ttl_count;
+        self.ttl_ms.merge(other.ttl_ms);
+        self.expiry_range = match (self.expiry_range, other.expiry_range) {
+            (Some((earliest_a, latest_a)), Some((earliest_b, latest_b))) => {
+                Some((earliest_a.min(earliest_b), latest_a.max(latest_b)))
+            }
+            (range_a, range_b) => range_a.or(range_b),
+        };
+        for (type_, count) in other.type_counts {
+            *self.type_counts.entry(type_).or_insert(0) += count;
+        }
+        for (type_, counts) in other.encoding_counts {
+            let self_counts = self.encoding_counts.entry(type_).or_default();
+            for (encoding, count) in counts {
+                *self_counts.entry(encoding).or_insert(0) += count;
+            }
+        }
+        self.count.merge(other.count);
+        self.idle.merge(other.idle);
+        self.freq.merge(other.freq);
+        for (key, memory) in other.top_by_memory {
+            add_top(&mut self.top_by_memory, self.n_top, &key, memory);
+        }
+        for (key, count) in other.top_by_count {
+            add_top(&mut self.top_by_count, self.n_top, &key, count);
+        }
+    }
+}
+
+// Inserts a key into a list of the `n` largest keys, if it's large enough. Ties are broken by key,
+// so the list is the same from run to run.
+fn add_top(top: &mut Vec<(Key, u64)>, n: usize, key: &Key, value: u64) {
+    let idx = top.partition_point(|(k, v)| (Reverse(*v), k) < (Reverse(value), key));
+    if idx < n {
+        top.insert(idx, (key.clone(), value));
+        top.truncate(n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::sample::SampleValue::*;
+
+    #[test]
+    fn bin_works() {
+        let mut bin = Bin::new(2);
+        let mut other = Bin::new(2);
+
+        for (key, memory, ttl_ms) in [
+            ("a", Sampled(10), Sampled(-1)),
+            ("b", Sampled(30), Sampled(5000)),
+            ("c", NotFound, Sampled(1000)),
+            ("d", Sampled(30), Unsampled),
+        ] {
+            let sample = Sample {
+                exists: Unsampled,
+                memory,
+                ttl_ms,
+                expires_at: Unsampled,
+                type_: Unsampled,
+                count: Unsampled,
+                encoding: Unsampled,
+                idle: Unsampled,
+                freq: Unsampled,
+            };
+            let bin = if key < "c" { &mut bin } else { &mut other };
+            bin.add(&key.as_bytes().to_vec(), &sample);
+        }
+
+        bin.merge(other);
+        assert_eq!(bin.key_count, 4);
+        assert_eq!(bin.example_keys.len(), 4);
+        assert_eq!(bin.memory.sum, 70);
+        assert_eq!(bin.memory.sum_of_squares, 1900.0);
+        assert_eq!(bin.memory.values.len(), 3);
+        assert_eq!(bin.ttl_count, 3);
+        assert_eq!(bin.ttl_ms.len(), 2);
+        assert_eq!(
+            bin.top_by_memory,
+            vec![(b"b".to_vec(), 30), (b"d".to_vec(), 30)]
+        );
+        assert!(bin.top_by_count.is_empty());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::data::bin::Bin;
+use crate::data::math::{self, pct_keyspace_sampled, Bucket};
+use crate::data::Data;
+use crate::metadata::Metadata;
+
+// Keys without an element count (like those of types added by modules) are left out of everything
+// here
+
+pub fn total(bin: &Bin) -> u64 {
+    bin.count.sum
+}
+
+pub fn total_estimate(metadata: &Metadata, data: &Data, bin: &Bin) -> u64 {
+    let pct_of_keyspace_sampled = pct_keyspace_sampled(metadata, data);
+    let sampled_total = total(bin);
+    (sampled_total as f64 / pct_of_keyspace_sampled).round() as u64
+}
+
+pub fn total_estimate_margin(metadata: &Metadata, data: &Data, bin: &Bin) -> Option<u64> {
+    math::total_estimate_margin(
+        metadata,
+        data,
+        bin.count.sum as f64,
+        bin.count.sum_of_squares,
+    )
+    .map(|m| m.round() as u64)
+}
+
+pub fn percentile(bin: &Bin, pct: f64) -> f64 {
+    bin.count.values.percentile(pct)
+}
+
+pub fn histogram(bin: &Bin, n_buckets: usize) -> Vec<Bucket> {
+    bin.count.values.histogram(n_buckets)
+}
@@ -0,0 +1,124 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::data::bin::Bin;
+
+// The encodings that OBJECT ENCODING can return for the core data types. Which ones are used
+// depends on the Redis version (e.g. "ziplist" was replaced by "listpack" in Redis 7.0).
+// https://redis.io/commands/object-encoding
+pub const REDIS_ENCODINGS: [&str; 11] = [
+    "raw",
+    "int",
+    "embstr",
+    "listpack",
+    "ziplist",
+    "quicklist",
+    "linkedlist",
+    "intset",
+    "hashtable",
+    "skiplist",
+    "stream",
+];
+
+pub fn encoding_pcts(bin: &Bin) -> Vec<(String, f64)> {
+    let total_count = bin.key_count;
+
+    let mut counts = HashMap::new();
+    for (encoding, count) in bin.encoding_counts.values().flatten() {
+        *counts.entry(encoding.clone()).or_insert(0) += count;
+    }
+
+    sorted_counts(counts)
+        .into_iter()
+        .map(|(encoding, count)| (encoding, (count as f64) / (total_count as f64) * 100.0))
+        .collect()
+}
+
+// Cross-tabulates encodings with types. For each type (most common first), this returns the
+// percentage of keys OF THAT TYPE with each encoding. So if 12% of the hashes in a bin have spilled
+// over from "listpack" to "hashtable", that shows up as ("hash", [("listpack", 88.0), ("hashtable",
+// 12.0)]).
+pub fn type_encoding_pcts(bin: &Bin) -> Vec<(String, Vec<(String, f64)>)> {
+    let mut by_type: Vec<(String, u64, &HashMap<String, u64>)> = bin
+        .encoding_counts
+        .iter()
+        .map(|(type_, counts)| (type_.clone(), counts.values().sum(), counts))
+        .collect();
+    by_type.sort_by(|(type_a, a, _), (type_b, b, _)| b.cmp(a).then(type_a.cmp(type_b)));
+
+    by_type
+        .into_iter()
+        .map(|(type_, type_count, counts)| {
+            let pcts = sorted_counts(counts.clone())
+                .into_iter()
+                .map(|(encoding, count)| (encoding, (count as f64) / (type_count as f64) * 100.0))
+                .collect();
+            (type_, pcts)
+        })
+        .collect()
+}
+
+// Sorts the counts of each distinct value, most common first (ties are broken alphabetically so the
+// output is stable)
+fn sorted_counts(counts: HashMap<String, u64>) -> Vec<(String, u64)> {
+    let mut sorted_counts: Vec<_> = counts.into_iter().collect();
+    sorted_counts.sort_by_key(|(value, count)| (Reverse(*count), value.clone()));
+    sorted_counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_encoding_pcts_works() {
+        use crate::sampling::sample::Sample;
+        use crate::sampling::sample::SampleValue::*;
+
+        let mut bin = Bin::new(0);
+
+        let types_and_encodings = [
+            ("hash", "listpack"),
+            ("hash", "hashtable"),
+            ("hash", "listpack"),
+            ("hash", "listpack"),
+            ("string", "embstr"),
+        ];
+        for (idx, (t, e)) in types_and_encodings.iter().enumerate() {
+            let sample = Sample {
+                exists: Unsampled,
+                memory: Unsampled,
+                ttl_ms: Unsampled,
+                expires_at: Unsampled,
+                type_: Sampled(t.to_string()),
+                count: Unsampled,
+                encoding: Sampled(e.to_string()),
+                idle: Unsampled,
+                freq: Unsampled,
+            };
+            bin.add(&format!("key_{}", idx).into_bytes(), &sample);
+        }
+
+        assert_eq!(
+            encoding_pcts(&bin),
+            vec![
+                ("listpack".to_string(), 60.0),
+                ("embstr".to_string(), 20.0),
+                ("hashtable".to_string(), 20.0),
+            ]
+        );
+        assert_eq!(
+            type_encoding_pcts(&bin),
+            vec![
+                (
+                    "hash".to_string(),
+                    vec![
+                        ("listpack".to_string(), 75.0),
+                        ("hashtable".to_string(), 25.0)
+                    ]
+                ),
+                ("string".to_string(), vec![("embstr".to_string(), 100.0)]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt::Write;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+// How keys (and parts of keys) that can't be shown as-is are shown: ones that aren't valid UTF-8,
+// or that have ASCII control characters (like newlines, which would break up rows of output)
+#[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
+pub enum KeyEscape {
+    // Bytes that aren't part of a valid UTF-8 character (and ASCII control characters) become
+    // "\xNN". This applies to every key, so backslashes always become "\\", and a key with the
+    // text "\xff" in it can't be mistaken for one with that byte.
+    Hex,
+    // The whole key is base64-encoded, with a "base64:" prefix. Other keys are shown as-is.
+    Base64,
+}
+
+impl KeyEscape {
+    pub fn escape(&self, key: &[u8]) -> String {
+        match self {
+            KeyEscape::Hex => {
+                let mut escaped = String::with_capacity(key.len() * 2);
+                for chunk in key.utf8_chunks() {
+                    for c in chunk.valid().chars() {
+                        match c {
+                            '\\' => escaped.push_str("\\\\"),
+                            c if c.is_ascii_control() => {
+                                write!(escaped, "\\x{:02x}", c as u8).unwrap()
+                            }
+                            c => escaped.push(c),
+                        }
+                    }
+                    for byte in chunk.invalid() {
+                        write!(escaped, "\\x{:02x}", byte).unwrap();
+                    }
+                }
+                escaped
+            }
+            KeyEscape::Base64 => match std::str::from_utf8(key) {
+                Ok(key) if !key.contains(|c: char| c.is_ascii_control()) => key.to_string(),
+                _ => format!("base64:{}", STANDARD.encode(key)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_works() {
+        for escape in [KeyEscape::Hex, KeyEscape::Base64] {
+            assert_eq!(escape.escape(b"user:1"), "user:1");
+            assert_eq!(escape.escape("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        }
+
+        // Valid UTF-8 is escaped too, so text can't pass for an escape, and rows stay on one line
+        assert_eq!(KeyEscape::Hex.escape(br"a\xff"), r"a\\xff");
+        assert_eq!(KeyEscape::Hex.escape(b"a\nb"), r"a\x0ab");
+        assert_eq!(KeyEscape::Base64.escape(br"a\xff"), r"a\xff");
+        assert_eq!(KeyEscape::Base64.escape(b"a\nb"), "base64:YQpi");
+
+        let key = b"\x0a\x03\xff\xfecaf\xc3\xa9\\:sessions";
+        assert_eq!(
+            KeyEscape::Hex.escape(key),
+            r"\x0a\x03\xff\xfecafé\\:sessions"
+        );
+        assert_eq!(
+            KeyEscape::Base64.escape(key),
+            "base64:CgP//mNhZsOpXDpzZXNzaW9ucw=="
+        );
+    }
+}
@@ -0,0 +1,13 @@
+use crate::data::bin::Bin;
+use crate::data::math::Bucket;
+
+// Note that these are Redis' logarithmic access frequency counters (from 0 to 255), not actual
+// numbers of accesses: https://redis.io/docs/reference/eviction/#the-new-lfu-mode
+
+pub fn percentile(bin: &Bin, pct: f64) -> f64 {
+    bin.freq.percentile(pct)
+}
+
+pub fn histogram(bin: &Bin, n_buckets: usize) -> Vec<Bucket> {
+    bin.freq.histogram(n_buckets)
+}
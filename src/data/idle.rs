@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use crate::data::bin::Bin;
+use crate::data::math::Bucket;
+
+// The percentage of keys that haven't been accessed for longer than `threshold`
+pub fn pct_idle_over(bin: &Bin, threshold: Duration) -> f64 {
+    if bin.idle.is_empty() {
+        0.0
+    } else {
+        let over_count = bin.idle.count_over(threshold.as_secs() as f64);
+        (over_count as f64) / (bin.idle.len() as f64) * 100.0
+    }
+}
+
+pub fn percentile(bin: &Bin, pct: f64) -> f64 {
+    bin.idle.percentile(pct).round()
+}
+
+pub fn histogram(bin: &Bin, n_buckets: usize) -> Vec<Bucket> {
+    bin.idle.histogram(n_buckets)
+}
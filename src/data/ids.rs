@@ -0,0 +1,54 @@
+use regex::Regex;
+
+// Recognizes key segments that look like IDs, which vary from key to key and so are replaced with
+// "*" when grouping keys: numbers, UUIDs, hex hashes, and dates
+#[derive(Debug)]
+pub struct IdMatcher {
+    number: Regex,
+    uuid: Regex,
+    hex: Regex,
+    date: Regex,
+}
+
+impl IdMatcher {
+    pub fn new() -> IdMatcher {
+        IdMatcher {
+            number: Regex::new(r"^\d+$").unwrap(),
+            uuid: Regex::new(r"^(?i)[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$")
+                .unwrap(),
+            hex: Regex::new(r"^(?i)[0-9a-f]*\d[0-9a-f]*$").unwrap(),
+            date: Regex::new(r"^\d{4}-\d{2}-\d{2}([T ]\d{2})?$").unwrap(),
+        }
+    }
+
+    pub fn is_id(&self, segment: &str) -> bool {
+        // Short hex-looking segments (like "be" or "cafe") are more likely to be words
+        let is_hex = self.hex.is_match(segment) && segment.len() >= 8;
+
+        self.number.is_match(segment)
+            || self.uuid.is_match(segment)
+            || is_hex
+            || self.date.is_match(segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_id_works() {
+        let ids = IdMatcher::new();
+        for id in [
+            "42",
+            "0b6f4ad4-1c4e-4f6e-9d1a-3f4b5c6d7e8f",
+            "d41d8cd98f00b204e9800998ecf8427e",
+            "2023-09-22",
+        ] {
+            assert!(ids.is_id(id), "{}", id);
+        }
+        for word in ["user", "cafe", "deadbeef", "v2", ""] {
+            assert!(!ids.is_id(word), "{}", word);
+        }
+    }
+}
@@ -1,8 +1,14 @@
-use crate::data::math::pct_keyspace_sampled;
-use crate::data::{Data, Keys};
+use crate::data::bin::Bin;
+use crate::data::math::{self, pct_keyspace_sampled};
+use crate::data::Data;
 use crate::metadata::Metadata;
 
-pub fn total_estimate(metadata: &Metadata, data: &Data, keys: &Keys) -> u64 {
+pub fn total_estimate(metadata: &Metadata, data: &Data, bin: &Bin) -> u64 {
     let pct_of_keyspace_sampled = pct_keyspace_sampled(metadata, data);
-    (keys.len() as f64 / pct_of_keyspace_sampled).round() as u64
+    (bin.key_count as f64 / pct_of_keyspace_sampled).round() as u64
+}
+
+pub fn total_estimate_margin(metadata: &Metadata, data: &Data, bin: &Bin) -> Option<u64> {
+    let key_count = bin.key_count as f64;
+    math::total_estimate_margin(metadata, data, key_count, key_count).map(|m| m.round() as u64)
 }
@@ -2,7 +2,7 @@ use crate::data::Data;
 use crate::metadata::Metadata;
 
 // Adapted from https://github.com/rust-lang/rust/blob/0f6f2d68/library/test/src/stats.rs#L259-L281
-pub fn percentile_of_sorted(sorted_samples: &Vec<f64>, pct: f64) -> f64 {
+pub fn percentile_of_sorted(sorted_samples: &[f64], pct: f64) -> f64 {
     assert!(!sorted_samples.is_empty());
     if sorted_samples.len() == 1 {
         return sorted_samples[0];
@@ -24,11 +24,151 @@ pub fn percentile_of_sorted(sorted_samples: &Vec<f64>, pct: f64) -> f64 {
     lo + (hi - lo) * d
 }
 
+// A range of values (inclusive of `lo`, exclusive of `hi`) and how many values fell within it
+#[derive(PartialEq, Debug)]
+pub struct Bucket {
+    pub lo: f64,
+    pub hi: f64,
+    pub count: usize,
+}
+
+// Splits the range between the smallest and largest values into `n_buckets` equally-sized buckets,
+// and counts the values in each. Each value comes with the number of times it occurs. The largest
+// value is counted in the last bucket.
+pub fn histogram(values: &[(f64, usize)], n_buckets: usize) -> Vec<Bucket> {
+    if values.is_empty() || n_buckets == 0 {
+        return vec![];
+    }
+
+    let min = values.iter().map(|(v, _)| *v).fold(f64::INFINITY, f64::min);
+    let max = values
+        .iter()
+        .map(|(v, _)| *v)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let width = (max - min) / n_buckets as f64;
+
+    let mut buckets: Vec<Bucket> = (0..n_buckets)
+        .map(|idx| Bucket {
+            lo: min + width * idx as f64,
+            hi: min + width * (idx + 1) as f64,
+            count: 0,
+        })
+        .collect();
+
+    for &(value, count) in values {
+        let idx = if width == 0_f64 {
+            0
+        } else {
+            (((value - min) / width) as usize).min(n_buckets - 1)
+        };
+        buckets[idx].count += count;
+    }
+
+    buckets
+}
+
 pub fn pct_keyspace_sampled(metadata: &Metadata, data: &Data) -> f64 {
-    let pct_of_keyspace_sampled = data.sample_count() as f64 / metadata.total_keys as f64;
-    if pct_of_keyspace_sampled > 100_f64 {
-        100_f64
+    let pct_of_keyspace_sampled = data.sample_count() as f64 / metadata.population() as f64;
+    if pct_of_keyspace_sampled > 1_f64 {
+        1_f64
     } else {
         pct_of_keyspace_sampled
     }
 }
+
+// The z-score for a two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+// The half-width of the 95% confidence interval around a bin's estimated total (as in
+// `total_estimate`), given the sum of the bin's values and the sum of their squares. Treating the
+// sample as a simple random sample of the population, every sampled key outside the bin counts as a
+// 0, so the same formula works both for estimating how many keys are in the bin (every value is 1)
+// and for estimating the bin's total memory or element count.
+//
+// When sampling with --match, the population is itself an estimate, and its own uncertainty isn't
+// included here. Returns None if there are too few samples to estimate the variance.
+pub fn total_estimate_margin(
+    metadata: &Metadata,
+    data: &Data,
+    sum: f64,
+    sum_of_squares: f64,
+) -> Option<f64> {
+    margin_of_error(
+        metadata.population(),
+        data.sample_count(),
+        sum,
+        sum_of_squares,
+    )
+}
+
+fn margin_of_error(
+    population: u64,
+    n_samples: usize,
+    sum: f64,
+    sum_of_squares: f64,
+) -> Option<f64> {
+    // Every key was sampled, so the total is exact
+    if n_samples as u64 >= population {
+        return Some(0_f64);
+    }
+    if n_samples < 2 {
+        return None;
+    }
+
+    let n = n_samples as f64;
+    let mean = sum / n;
+    let variance = ((sum_of_squares - n * mean * mean) / (n - 1_f64)).max(0_f64);
+
+    // The finite population correction, since keys are sampled without replacement
+    let population = population as f64;
+    let fpc = (population - n) / (population - 1_f64);
+
+    Some(Z_95 * population * (variance / n * fpc).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_works() {
+        assert_eq!(
+            histogram(&[(0.0, 1), (1.0, 1), (2.0, 1), (9.0, 1), (10.0, 1)], 2),
+            vec![
+                Bucket {
+                    lo: 0.0,
+                    hi: 5.0,
+                    count: 3
+                },
+                Bucket {
+                    lo: 5.0,
+                    hi: 10.0,
+                    count: 2
+                },
+            ]
+        );
+        assert_eq!(
+            histogram(&[(3.0, 2)], 4)
+                .iter()
+                .map(|b| b.count)
+                .collect::<Vec<_>>(),
+            vec![2, 0, 0, 0]
+        );
+        assert_eq!(histogram(&[], 4), vec![]);
+    }
+
+    #[test]
+    fn margin_of_error_works() {
+        // 20 of 100 sampled keys (from 1000) are in the bin, so the estimate is 200 keys
+        let margin = margin_of_error(1000, 100, 20.0, 20.0).unwrap();
+        assert!((margin - 74.79).abs() < 0.01, "{}", margin);
+
+        // Scaling every value scales the margin too
+        let margin = margin_of_error(1000, 100, 50.0 * 20.0, 2500.0 * 20.0).unwrap();
+        assert!((margin - 3739.44).abs() < 0.01, "{}", margin);
+
+        assert_eq!(margin_of_error(1000, 1000, 20.0, 20.0), Some(0.0));
+        assert_eq!(margin_of_error(1000, 100, 0.0, 0.0), Some(0.0));
+        assert_eq!(margin_of_error(1000, 1, 1.0, 1.0), None);
+    }
+}
@@ -1,5 +1,9 @@
-use crate::data::Keys;
+use crate::data::bin::Bin;
+use crate::data::escape::KeyEscape;
 
-pub fn example_keys(keys: &Keys) -> Keys {
-    keys.clone().into_iter().take(5).collect()
+pub fn example_keys(bin: &Bin, key_escape: &KeyEscape) -> Vec<String> {
+    bin.example_keys
+        .iter()
+        .map(|key| key_escape.escape(key))
+        .collect()
 }
@@ -0,0 +1,388 @@
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::data::escape::KeyEscape;
+
+// Patterns starting with this are regexes instead of globs
+const REGEX_PREFIX: &str = "re:";
+
+// A pattern to group keys by: either a glob, or (with a "re:" prefix) a regex. A regex with named
+// capture groups puts keys into one bin per distinct captured value, so
+// "re:^tenant:(?P<tenant>[^:]+):" has a bin for each tenant.
+//
+// Regexes match keys' raw bytes, and Unicode mode is off by default so that "." and "[^:]" match
+// any byte (including ones that aren't valid UTF-8), and "\xff" matches that byte. It can be turned
+// back on with "(?u)". Globs match raw bytes too, exactly like SCAN's MATCH (see glob_matches).
+#[derive(Clone, Debug)]
+pub enum KeyPattern {
+    Glob(Glob),
+    Regex(Regex),
+}
+
+impl KeyPattern {
+    // The pattern as it was given, including the "re:" prefix for regexes
+    pub fn as_str(&self) -> String {
+        match self {
+            KeyPattern::Glob(glob) => glob.as_str().to_string(),
+            KeyPattern::Regex(regex) => format!("{}{}", REGEX_PREFIX, regex.as_str()),
+        }
+    }
+
+    pub fn matches(&self, key: &[u8]) -> bool {
+        match self {
+            KeyPattern::Glob(glob) => glob_matches(glob, key),
+            KeyPattern::Regex(regex) => regex.is_match(key),
+        }
+    }
+
+    pub fn has_named_captures(&self) -> bool {
+        match self {
+            KeyPattern::Glob(_) => false,
+            KeyPattern::Regex(regex) => regex.capture_names().flatten().next().is_some(),
+        }
+    }
+
+    // The name of the bin the given key belongs in, if it matches. That's the pattern itself,
+    // unless it has named capture groups, in which case it's their values (like "tenant=acme",
+    // escaped like keys are). Groups that didn't participate in the match have empty values.
+    pub fn bin_name(&self, key: &[u8], key_escape: &KeyEscape) -> Option<String> {
+        match self {
+            KeyPattern::Regex(regex) if self.has_named_captures() => {
+                let captures = regex.captures(key)?;
+                let values: Vec<String> = regex
+                    .capture_names()
+                    .flatten()
+                    .map(|name| {
+                        let value = captures.name(name).map_or(&b""[..], |m| m.as_bytes());
+                        format!("{}={}", name, key_escape.escape(value))
+                    })
+                    .collect();
+                Some(values.join(","))
+            }
+            _ => self.matches(key).then(|| self.as_str()),
+        }
+    }
+}
+
+// A pattern, along with an optional label that its bins are shown as instead (in the table and HTML
+// outputs). Labels are given separately from patterns (with --label, or in a profile), so any glob
+// can be given as a pattern as-is, "=" and all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinDef {
+    pub label: Option<String>,
+    pub pattern: KeyPattern,
+}
+
+impl BinDef {
+    // The label to show for the given bin of this pattern (see KeyPattern::bin_name), if it has
+    // one. Bins of patterns with named captures have their names added, like "Sessions
+    // (tenant=acme)".
+    pub fn bin_label(&self, bin_name: &str) -> Option<String> {
+        let label = self.label.as_ref()?;
+        if self.pattern.has_named_captures() {
+            Some(format!("{} ({})", label, bin_name))
+        } else {
+            Some(label.clone())
+        }
+    }
+}
+
+impl FromStr for BinDef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BinDef, String> {
+        s.parse().map(KeyPattern::into)
+    }
+}
+
+impl From<KeyPattern> for BinDef {
+    fn from(pattern: KeyPattern) -> BinDef {
+        BinDef {
+            label: None,
+            pattern,
+        }
+    }
+}
+
+// A glob, as it was given. Redis gives every glob a meaning (see string_match for the syntax), so
+// there's nothing to validate: "a**b" is the same as "a*b", and a class that's never closed runs to
+// the end of the glob.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Glob(String);
+
+impl Glob {
+    pub fn new(glob: &str) -> Glob {
+        Glob(glob.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Glob {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Glob, Infallible> {
+        Ok(Glob::new(s))
+    }
+}
+
+// Globs are matched against keys' raw bytes exactly like SCAN's MATCH does, so a key is binned the
+// same way whether it was sampled at random or found by a scan for that glob. Like SCAN, "*"
+// matches every key without going through string_match (which is the only way it matches an empty
+// key).
+pub fn glob_matches(glob: &Glob, key: &[u8]) -> bool {
+    glob.as_str() == "*" || string_match(glob.as_str().as_bytes(), key, &mut false)
+}
+
+// A port of stringmatchlen from Redis' util.c, which SCAN's MATCH (and KEYS) use: "?" matches any
+// one byte, "*" any run of bytes, "[...]" any byte in the class ("[^...]" any byte not in it, and
+// "a-z" a range), and "\" escapes the next byte (in or out of a class). A class that's never closed
+// runs to the end of the pattern.
+//
+// Once a "*" has tried every remaining suffix of the key without a match, skip_longer stops any
+// "*"s before it from trying again with even shorter suffixes, which would fail the same way.
+fn string_match(pattern: &[u8], key: &[u8], skip_longer: &mut bool) -> bool {
+    let (mut p, mut k) = (0, 0);
+
+    while p < pattern.len() && k < key.len() {
+        match pattern[p] {
+            b'*' => {
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                while k < key.len() {
+                    if string_match(&pattern[p + 1..], &key[k..], skip_longer) {
+                        return true;
+                    }
+                    if *skip_longer {
+                        return false;
+                    }
+                    k += 1;
+                }
+                *skip_longer = true;
+                return false;
+            }
+            b'?' => k += 1,
+            b'[' => {
+                p += 1;
+                let negated = pattern.get(p) == Some(&b'^');
+                if negated {
+                    p += 1;
+                }
+                let mut is_match = false;
+                loop {
+                    if p + 1 < pattern.len() && pattern[p] == b'\\' {
+                        p += 1;
+                        is_match |= pattern[p] == key[k];
+                    } else if p == pattern.len() {
+                        p -= 1;
+                        break;
+                    } else if pattern[p] == b']' {
+                        break;
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (lo, hi) = (pattern[p], pattern[p + 2]);
+                        is_match |= (lo.min(hi)..=lo.max(hi)).contains(&key[k]);
+                        p += 2;
+                    } else {
+                        is_match |= pattern[p] == key[k];
+                    }
+                    p += 1;
+                }
+                if is_match == negated {
+                    return false;
+                }
+                k += 1;
+            }
+            byte => {
+                if byte == b'\\' && p + 1 < pattern.len() {
+                    p += 1;
+                }
+                if pattern[p] != key[k] {
+                    return false;
+                }
+                k += 1;
+            }
+        }
+
+        p += 1;
+        if k == key.len() {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            break;
+        }
+    }
+
+    p == pattern.len() && k == key.len()
+}
+
+impl FromStr for KeyPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<KeyPattern, String> {
+        match s.strip_prefix(REGEX_PREFIX) {
+            Some(regex) => RegexBuilder::new(regex)
+                .unicode(false)
+                .build()
+                .map(KeyPattern::Regex)
+                .map_err(|e| e.to_string()),
+            None => Ok(KeyPattern::Glob(Glob::new(s))),
+        }
+    }
+}
+
+impl From<Glob> for KeyPattern {
+    fn from(glob: Glob) -> KeyPattern {
+        KeyPattern::Glob(glob)
+    }
+}
+
+// Regexes can't be compared themselves, so patterns are compared by how they were given
+
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &KeyPattern) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for KeyPattern {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_pattern_works() {
+        let hex = KeyEscape::Hex;
+
+        let glob: KeyPattern = "user:*".parse().unwrap();
+        assert_eq!(glob.bin_name(b"user:1", &hex), Some("user:*".to_string()));
+        assert_eq!(glob.bin_name(b"company:1", &hex), None);
+        assert!(glob.matches(b"user:\xff"));
+
+        let regex: KeyPattern = r"re:^user:\d+$".parse().unwrap();
+        assert_eq!(regex.as_str(), r"re:^user:\d+$");
+        assert!(!regex.has_named_captures());
+        assert_eq!(
+            regex.bin_name(b"user:1", &hex),
+            Some(r"re:^user:\d+$".to_string())
+        );
+        assert_eq!(regex.bin_name(b"user:abc", &hex), None);
+
+        let captures: KeyPattern = "re:^tenant:(?P<tenant>[^:]+):(?:(?P<kind>sessions)|.*)$"
+            .parse()
+            .unwrap();
+        assert!(captures.has_named_captures());
+        assert_eq!(
+            captures.bin_name(b"tenant:acme:sessions", &hex),
+            Some("tenant=acme,kind=sessions".to_string())
+        );
+        assert_eq!(
+            captures.bin_name(b"tenant:globex:cache", &hex),
+            Some("tenant=globex,kind=".to_string())
+        );
+        assert_eq!(
+            captures.bin_name(b"tenant:\x0a\xff:cache", &hex),
+            Some(r"tenant=\x0a\xff,kind=".to_string())
+        );
+        assert_eq!(captures.bin_name(b"user:1", &hex), None);
+
+        // Raw bytes can be matched, and Unicode mode can be turned back on
+        let bytes: KeyPattern = r"re:^\x0a\xff".parse().unwrap();
+        assert!(bytes.matches(b"\x0a\xff:sessions"));
+        assert!(!bytes.matches(b"user:1"));
+        let unicode: KeyPattern = r"re:(?u)^\w+$".parse().unwrap();
+        assert!(unicode.matches("caf\u{e9}".as_bytes()));
+
+        assert!("re:(".parse::<KeyPattern>().is_err());
+
+        // Globs that Redis accepts are never rejected, even if they'd be invalid elsewhere
+        let stars: KeyPattern = "user:**#memes".parse().unwrap();
+        assert!(stars.matches(b"user:1#memes"));
+        let unclosed: KeyPattern = "bin:[ab".parse().unwrap();
+        assert!(unclosed.matches(b"bin:b"));
+        assert!(!unclosed.matches(b"bin:c"));
+    }
+
+    #[test]
+    fn glob_matches_works() {
+        let matches = |glob: &str, key: &[u8]| glob_matches(&Glob::new(glob), key);
+
+        assert!(matches("*", b""));
+        assert!(matches("*", b"\xff\x00"));
+        assert!(matches("user:*#messages", b"user:1#messages"));
+        assert!(matches("user:*#messages", b"user:#messages"));
+        assert!(!matches("user:*#messages", b"user:1#memes"));
+        assert!(matches("a*b*c", b"aXbYbZc"));
+        assert!(!matches("a*b*c", b"aXbYbZ"));
+        assert!(matches("a*", b"a"));
+        assert!(!matches("**", b""));
+
+        // "?" matches one byte, even in the middle of a UTF-8 character
+        assert!(matches("bin:?", b"bin:\xff"));
+        assert!(!matches("bin:?", "bin:\u{e9}".as_bytes()));
+        assert!(matches("bin:??", "bin:\u{e9}".as_bytes()));
+
+        // Classes, negated with "^" (not "!"), with ranges in either order
+        assert!(matches("[a-c]1", b"b1"));
+        assert!(matches("[c-a]1", b"b1"));
+        assert!(!matches("[^a-c]1", b"b1"));
+        assert!(matches("[!a]", b"!"));
+        assert!(matches("[=]", b"="));
+        assert!(matches("[\\]]", b"]"));
+        assert!(!matches("[]]", b"]"));
+
+        // Backslashes escape the next byte
+        assert!(matches(r"a\*", b"a*"));
+        assert!(!matches(r"a\*", b"ab"));
+        assert!(matches(r"a\?", b"a?"));
+        assert!(matches(r"a\\", b"a\\"));
+    }
+
+    #[test]
+    fn bin_def_works() {
+        let def = BinDef {
+            label: Some("User inboxes".to_string()),
+            pattern: "user:*#messages".parse().unwrap(),
+        };
+        assert_eq!(
+            def.bin_label("user:*#messages").as_deref(),
+            Some("User inboxes")
+        );
+
+        let def = BinDef {
+            label: Some("Sessions".to_string()),
+            pattern: "re:^tenant:(?P<tenant>[^:]+):sessions$".parse().unwrap(),
+        };
+        assert_eq!(
+            def.bin_label("tenant=acme").as_deref(),
+            Some("Sessions (tenant=acme)")
+        );
+
+        // Globs with "=" in them keep their meaning, rather than being read as labels
+        for pattern in [
+            "user:id=*",
+            "type=web:*",
+            "session[=]*",
+            "*=*",
+            "=x",
+            r"re:^a=\d$",
+        ] {
+            let def: BinDef = pattern.parse().unwrap();
+            assert_eq!(def.label, None);
+            assert_eq!(def.pattern.as_str(), pattern);
+            assert_eq!(def.bin_label(pattern), None);
+        }
+        let def: BinDef = "user:id=*".parse().unwrap();
+        assert!(def.pattern.matches(b"user:id=42"));
+
+        assert!("re:(".parse::<BinDef>().is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use ring::hmac;
+
+use crate::data::pattern::KeyPattern;
+use crate::data::Key;
+
+// What the parts of keys matched by wildcards are replaced with
+#[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
+pub enum Redact {
+    // A keyed hash like "<9f86d081>", so the same value always looks the same (within a run, or
+    // across runs with --redact-key) without being reversible
+    Hash,
+    // "***"
+    Mask,
+}
+
+const MASK: &str = "***";
+
+// How many bytes of the HMAC to show; 4 bytes is plenty to tell values apart in a report
+const HASH_BYTES: usize = 4;
+
+// Hides the parts of keys that could hold sensitive data (like customer emails), for --redact.
+// Literal parts of the glob that a key was binned by are kept, and each run of characters matched
+// by wildcards ("*", "?", and "[...]") is replaced, so "user:*#messages" shows keys like
+// "user:<9f86d081>#messages". Regexes have no literal parts we can pick out, so keys binned by one
+// are replaced entirely.
+#[derive(Debug)]
+pub struct Redactor {
+    mode: Redact,
+    key: hmac::Key,
+}
+
+impl Redactor {
+    pub fn new(mode: Redact, secret: &str) -> Redactor {
+        Redactor {
+            mode,
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        }
+    }
+
+    pub fn redact(&self, pattern: &KeyPattern, key: &[u8]) -> Key {
+        let text = String::from_utf8_lossy(key);
+        let chars: Vec<char> = text.chars().collect();
+
+        let tokens = match pattern {
+            KeyPattern::Glob(glob) => tokenize(glob.as_str()),
+            KeyPattern::Regex(_) => vec![Token::AnySequence],
+        };
+        let spans = match match_tokens(&tokens, &chars) {
+            Some(spans) => spans,
+            None => return self.replace(&text).into_bytes(),
+        };
+
+        let mut redacted = String::with_capacity(key.len());
+        let mut wildcard_text = String::new();
+        let mut in_wildcards = false;
+        for (token, (start, end)) in tokens.iter().zip(spans) {
+            if let Token::Literal(c) = token {
+                if in_wildcards {
+                    redacted.push_str(&self.replace(&wildcard_text));
+                    wildcard_text.clear();
+                    in_wildcards = false;
+                }
+                redacted.push(*c);
+            } else {
+                wildcard_text.extend(&chars[start..end]);
+                in_wildcards = true;
+            }
+        }
+        if in_wildcards {
+            redacted.push_str(&self.replace(&wildcard_text));
+        }
+
+        redacted.into_bytes()
+    }
+
+    fn replace(&self, text: &str) -> String {
+        match self.mode {
+            Redact::Mask => MASK.to_string(),
+            Redact::Hash => {
+                let tag = hmac::sign(&self.key, text.as_bytes());
+                let mut hash = String::from("<");
+                for byte in &tag.as_ref()[..HASH_BYTES] {
+                    write!(hash, "{:02x}", byte).unwrap();
+                }
+                hash.push('>');
+                hash
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    AnySequence,
+    // The ranges of characters in a "[...]" (a single character is a range of one)
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(literal) => *literal == c,
+            Token::AnyChar => true,
+            Token::AnySequence => false,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+// Splits a glob into tokens, following the same syntax as glob_matches (see string_match in
+// pattern.rs): "[^...]" is a negated class, "\" escapes the next character (in or out of a class),
+// and a class that's never closed runs to the end of the glob
+fn tokenize(glob: &str) -> Vec<Token> {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut tokens = vec![];
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        match chars[idx] {
+            '?' => {
+                tokens.push(Token::AnyChar);
+                idx += 1;
+            }
+            '*' => {
+                while chars.get(idx) == Some(&'*') {
+                    idx += 1;
+                }
+                tokens.push(Token::AnySequence);
+            }
+            '\\' if idx + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[idx + 1]));
+                idx += 2;
+            }
+            '[' => {
+                let negated = chars.get(idx + 1) == Some(&'^');
+                let mut member = idx + 1 + negated as usize;
+                let mut ranges = vec![];
+                while member < chars.len() {
+                    if chars[member] == '\\' && member + 1 < chars.len() {
+                        ranges.push((chars[member + 1], chars[member + 1]));
+                        member += 2;
+                    } else if chars[member] == ']' {
+                        member += 1;
+                        break;
+                    } else if member + 2 < chars.len() && chars[member + 1] == '-' {
+                        let (lo, hi) = (chars[member], chars[member + 2]);
+                        ranges.push((lo.min(hi), lo.max(hi)));
+                        member += 3;
+                    } else {
+                        ranges.push((chars[member], chars[member]));
+                        member += 1;
+                    }
+                }
+                // A class of one character is just an escaped literal, like "[*]"
+                match ranges[..] {
+                    [(lo, hi)] if !negated && lo == hi => tokens.push(Token::Literal(lo)),
+                    _ => tokens.push(Token::Class { negated, ranges }),
+                }
+                idx = member;
+            }
+            c => {
+                tokens.push(Token::Literal(c));
+                idx += 1;
+            }
+        }
+    }
+
+    tokens
+}
+
+// Returns the range of characters that each token matched, if the tokens match all of `chars`
+fn match_tokens(tokens: &[Token], chars: &[char]) -> Option<Vec<(usize, usize)>> {
+    let mut spans = vec![(0, 0); tokens.len()];
+    let mut failed = HashSet::new();
+    match_from(tokens, chars, 0, 0, &mut spans, &mut failed).then_some(spans)
+}
+
+// Backtracks over where each "*" ends, remembering positions that can't match so this stays
+// O(tokens * chars)
+fn match_from(
+    tokens: &[Token],
+    chars: &[char],
+    token_idx: usize,
+    char_idx: usize,
+    spans: &mut Vec<(usize, usize)>,
+    failed: &mut HashSet<(usize, usize)>,
+) -> bool {
+    if token_idx == tokens.len() {
+        return char_idx == chars.len();
+    }
+    if failed.contains(&(token_idx, char_idx)) {
+        return false;
+    }
+
+    let is_match = match &tokens[token_idx] {
+        Token::AnySequence => (char_idx..=chars.len()).any(|end| {
+            spans[token_idx] = (char_idx, end);
+            match_from(tokens, chars, token_idx + 1, end, spans, failed)
+        }),
+        token => {
+            chars.get(char_idx).is_some_and(|&c| token.matches(c)) && {
+                spans[token_idx] = (char_idx, char_idx + 1);
+                match_from(tokens, chars, token_idx + 1, char_idx + 1, spans, failed)
+            }
+        }
+    };
+
+    if !is_match {
+        failed.insert((token_idx, char_idx));
+    }
+    is_match
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(redactor: &Redactor, pattern: &str, key: &[u8]) -> String {
+        let pattern: KeyPattern = pattern.parse().unwrap();
+        String::from_utf8(redactor.redact(&pattern, key)).unwrap()
+    }
+
+    #[test]
+    fn redact_works() {
+        let mask = Redactor::new(Redact::Mask, "");
+        assert_eq!(
+            redact(&mask, "user:*#messages", b"user:bob@example.com#messages"),
+            "user:***#messages"
+        );
+        assert_eq!(redact(&mask, "user:?[0-9]*", b"user:a12"), "user:***");
+        assert_eq!(redact(&mask, r"[[]x\]:*", b"[x]:secret"), "[x]:***");
+        assert_eq!(redact(&mask, "*", b"\xffsecret"), "***");
+        assert_eq!(redact(&mask, r"re:^user:\d+$", b"user:1"), "***");
+
+        // Hashes are the same for the same values (and secret), and differ otherwise
+        let hash = Redactor::new(Redact::Hash, "secret");
+        let alice = redact(&hash, "user:*:*", b"user:alice:sessions");
+        assert_eq!(alice.len(), "user:<12345678>:<12345678>".len());
+        assert!(alice.starts_with("user:<"));
+        assert_eq!(alice, redact(&hash, "user:*:*", b"user:alice:sessions"));
+        assert_ne!(alice, redact(&hash, "user:*:*", b"user:bob:sessions"));
+        let other_secret = Redactor::new(Redact::Hash, "other");
+        assert_ne!(
+            alice,
+            redact(&other_secret, "user:*:*", b"user:alice:sessions")
+        );
+    }
+
+    #[test]
+    fn tokenize_works() {
+        assert_eq!(
+            tokenize("a?**[^d-b]"),
+            vec![
+                Token::Literal('a'),
+                Token::AnyChar,
+                Token::AnySequence,
+                Token::Class {
+                    negated: true,
+                    ranges: vec![('b', 'd')]
+                },
+            ]
+        );
+        assert_eq!(
+            tokenize(r"\*[\]][ab\-][x"),
+            vec![
+                Token::Literal('*'),
+                Token::Literal(']'),
+                Token::Class {
+                    negated: false,
+                    ranges: vec![('a', 'a'), ('b', 'b'), ('-', '-')]
+                },
+                Token::Literal('x'),
+            ]
+        );
+    }
+}
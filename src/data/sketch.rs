@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use crate::data::math::{self, percentile_of_sorted, Bucket};
+
+// Values are kept exactly until there are this many of them, so percentiles of a typical random
+// sample are exact. After that, they're bucketed so memory use stays flat.
+const MAX_EXACT_VALUES: usize = 10_000;
+
+// Once values are bucketed, percentiles are within this fraction of the true value
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+// A streaming summary of non-negative values, for percentiles and histograms. Bucketing works like
+// DDSketch (https://arxiv.org/abs/1908.10693): each bucket covers a range of values that grows
+// geometrically, so a bucket's midpoint is never more than RELATIVE_ACCURACY away from any value in
+// it, and there are only a few thousand buckets between 1 byte and 1 TB. Zeros get a bucket of
+// their own.
+#[derive(Debug)]
+pub struct Sketch {
+    count: u64,
+    min: f64,
+    max: f64,
+    values: Values,
+}
+
+#[derive(Debug)]
+enum Values {
+    Exact(Vec<f64>),
+    Bucketed {
+        zeros: u64,
+        buckets: BTreeMap<i32, u64>,
+    },
+}
+
+impl Default for Sketch {
+    fn default() -> Sketch {
+        Sketch {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            values: Values::Exact(vec![]),
+        }
+    }
+}
+
+impl Sketch {
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.add_n(value, 1);
+    }
+
+    // Moves all of the values from another Sketch into this one
+    pub fn merge(&mut self, other: Sketch) {
+        match other.values {
+            Values::Exact(values) => {
+                for value in values {
+                    self.add(value);
+                }
+            }
+            Values::Bucketed { zeros, buckets } => {
+                // Bucket midpoints may be slightly outside of the other sketch's real range
+                let min = self.min.min(other.min);
+                let max = self.max.max(other.max);
+
+                self.bucket_values();
+                self.add_n(0_f64, zeros);
+                for (idx, count) in buckets {
+                    self.add_n(bucket_value(idx), count);
+                }
+                self.min = min;
+                self.max = max;
+            }
+        }
+    }
+
+    pub fn percentile(&self, pct: f64) -> f64 {
+        match &self.values {
+            _ if self.count == 0 => 0_f64,
+            Values::Exact(values) => {
+                let mut sorted = values.clone();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                percentile_of_sorted(&sorted, pct)
+            }
+            Values::Bucketed { .. } => {
+                let rank = (pct / 100_f64 * (self.count - 1) as f64).round() as u64;
+                let mut seen = 0;
+                for (value, count) in self.counted_values() {
+                    seen += count as u64;
+                    if seen > rank {
+                        return value;
+                    }
+                }
+                self.max
+            }
+        }
+    }
+
+    // The number of values greater than `threshold`
+    pub fn count_over(&self, threshold: f64) -> u64 {
+        self.counted_values()
+            .filter(|(value, _)| *value > threshold)
+            .map(|(_, count)| count as u64)
+            .sum()
+    }
+
+    pub fn histogram(&self, n_buckets: usize) -> Vec<Bucket> {
+        let values: Vec<(f64, usize)> = self.counted_values().collect();
+        math::histogram(&values, n_buckets)
+    }
+
+    fn add_n(&mut self, value: f64, n: u64) {
+        if n == 0 {
+            return;
+        }
+        self.count += n;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        match &mut self.values {
+            Values::Exact(values) => {
+                values.extend((0..n).map(|_| value));
+                if values.len() > MAX_EXACT_VALUES {
+                    self.bucket_values();
+                }
+            }
+            Values::Bucketed { zeros, buckets } => {
+                if value <= 0_f64 {
+                    *zeros += n;
+                } else {
+                    *buckets.entry(bucket_index(value)).or_insert(0) += n;
+                }
+            }
+        }
+    }
+
+    // Switches from exact values to buckets (if that hasn't already happened)
+    fn bucket_values(&mut self) {
+        if let Values::Exact(values) = &mut self.values {
+            let mut zeros = 0;
+            let mut buckets = BTreeMap::new();
+            for &value in values.iter() {
+                if value <= 0_f64 {
+                    zeros += 1;
+                } else {
+                    *buckets.entry(bucket_index(value)).or_insert(0) += 1;
+                }
+            }
+            self.values = Values::Bucketed { zeros, buckets };
+        }
+    }
+
+    // Every value (or bucket midpoint, clamped to the range of values actually seen) in ascending
+    // order, along with how many times it occurs
+    fn counted_values(&self) -> Box<dyn Iterator<Item = (f64, usize)> + '_> {
+        match &self.values {
+            Values::Exact(values) => Box::new(values.iter().map(|&v| (v, 1))),
+            Values::Bucketed { zeros, buckets } => Box::new(
+                std::iter::once((0_f64, *zeros as usize))
+                    .filter(|(_, count)| *count > 0)
+                    .chain(buckets.iter().map(|(&idx, &count)| {
+                        (bucket_value(idx).clamp(self.min, self.max), count as usize)
+                    })),
+            ),
+        }
+    }
+}
+
+fn gamma() -> f64 {
+    (1_f64 + RELATIVE_ACCURACY) / (1_f64 - RELATIVE_ACCURACY)
+}
+
+fn bucket_index(value: f64) -> i32 {
+    (value.ln() / gamma().ln()).ceil() as i32
+}
+
+fn bucket_value(idx: i32) -> f64 {
+    2_f64 * gamma().powi(idx) / (gamma() + 1_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sketch_works() {
+        let mut exact = Sketch::default();
+        assert_eq!(exact.percentile(50.0), 0.0);
+        for value in [4.0, 1.0, 3.0, 2.0] {
+            exact.add(value);
+        }
+        assert_eq!(exact.percentile(50.0), 2.5);
+        assert_eq!(exact.count_over(2.0), 2);
+
+        // Past MAX_EXACT_VALUES, percentiles are only approximate
+        let mut bucketed = Sketch::default();
+        for value in 0..=100_000 {
+            bucketed.add(value as f64);
+        }
+        assert_eq!(bucketed.len(), 100_001);
+        assert_eq!(bucketed.percentile(0.0), 0.0);
+        for pct in [1.0, 50.0, 90.0, 99.0, 100.0] {
+            let expected = pct * 1000.0;
+            let actual = bucketed.percentile(pct);
+            assert!((actual - expected).abs() <= expected * 0.02, "{}", actual);
+        }
+        let over = bucketed.count_over(50_000.0) as f64;
+        assert!((over - 50_000.0).abs() <= 1_000.0, "{}", over);
+
+        // Merging an exact sketch into a bucketed one (and vice versa) keeps every value
+        bucketed.merge(exact);
+        assert_eq!(bucketed.len(), 100_005);
+        let mut merged = Sketch::default();
+        merged.merge(bucketed);
+        assert_eq!(merged.len(), 100_005);
+        let total: usize = merged.histogram(10).iter().map(|b| b.count).sum();
+        assert_eq!(total, 100_005);
+    }
+}
@@ -0,0 +1,19 @@
+use crate::data::bin::Bin;
+use crate::data::escape::KeyEscape;
+use crate::data::Key;
+
+// The --top keys with the largest memory usage, along with their memory usage (largest first)
+pub fn by_memory(bin: &Bin, key_escape: &KeyEscape) -> Vec<(String, u64)> {
+    escape_keys(&bin.top_by_memory, key_escape)
+}
+
+// The --top keys with the most elements, along with their element counts (largest first)
+pub fn by_count(bin: &Bin, key_escape: &KeyEscape) -> Vec<(String, u64)> {
+    escape_keys(&bin.top_by_count, key_escape)
+}
+
+fn escape_keys(top: &[(Key, u64)], key_escape: &KeyEscape) -> Vec<(String, u64)> {
+    top.iter()
+        .map(|(key, value)| (key_escape.escape(key), *value))
+        .collect()
+}
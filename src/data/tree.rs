@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use crate::data::escape::KeyEscape;
+use crate::data::ids::IdMatcher;
+use crate::sampling::sample::Sample;
+
+// How many distinct children a prefix can have before any new ones are lumped together, so a
+// segment that's unique per key (but doesn't look like an ID) can't make the tree grow with the
+// number of keys sampled
+const MAX_CHILDREN: usize = 100;
+
+// The segment that children past MAX_CHILDREN are lumped into
+pub const OTHER_SEGMENT: &str = "(other)";
+
+// Keys split into prefixes on --delimiter, for -o tree. Every prefix (up to --tree-depth segments
+// long) has its own counts, so it's easy to see where memory is going without knowing the key
+// layout in advance. Segments that look like IDs are replaced with "*", so "user:1:sessions" and
+// "user:2:sessions" both count towards "user", "user:*", and "user:*:sessions". Keys are split as
+// bytes, and then each segment is escaped (if it isn't valid UTF-8) on its own.
+#[derive(Debug)]
+pub struct PrefixTree {
+    pub root: PrefixNode,
+    delimiter: char,
+    max_depth: usize,
+    key_escape: KeyEscape,
+    ids: IdMatcher,
+}
+
+#[derive(Debug, Default)]
+pub struct PrefixNode {
+    pub key_count: u64,
+    pub memory: u64,
+    // For the margin of error of the estimated total memory
+    pub memory_sum_of_squares: f64,
+    // The number of keys whose TTL was sampled, and how many of those have one
+    pub ttl_count: u64,
+    pub with_ttl_count: u64,
+    pub children: HashMap<String, PrefixNode>,
+}
+
+impl PrefixTree {
+    pub fn new(delimiter: char, max_depth: usize, key_escape: KeyEscape) -> PrefixTree {
+        PrefixTree {
+            root: PrefixNode::default(),
+            delimiter,
+            max_depth,
+            key_escape,
+            ids: IdMatcher::new(),
+        }
+    }
+
+    pub fn delimiter(&self) -> char {
+        self.delimiter
+    }
+
+    pub fn add(&mut self, key: &[u8], sample: &Sample) {
+        let mut node = &mut self.root;
+        node.add(sample);
+
+        let mut delimiter = [0; 4];
+        let delimiter = self.delimiter.encode_utf8(&mut delimiter).as_bytes();
+        for segment in split(key, delimiter).into_iter().take(self.max_depth) {
+            let segment = self.key_escape.escape(segment);
+            let segment = if self.ids.is_id(&segment) {
+                "*"
+            } else {
+                &segment
+            };
+            node = node.child(segment);
+            node.add(sample);
+        }
+    }
+
+    // Moves all of the counts from another PrefixTree into this one
+    pub fn merge(&mut self, other: PrefixTree) {
+        self.root.merge(other.root);
+    }
+}
+
+impl PrefixNode {
+    fn add(&mut self, sample: &Sample) {
+        self.key_count += 1;
+        if let Some(&memory) = sample.memory.get() {
+            self.memory += memory;
+            self.memory_sum_of_squares += (memory as f64).powi(2);
+        }
+        if let Some(&ttl_ms) = sample.ttl_ms.get() {
+            self.ttl_count += 1;
+            if ttl_ms >= 0 {
+                self.with_ttl_count += 1;
+            }
+        }
+    }
+
+    fn merge(&mut self, other: PrefixNode) {
+        self.key_count += other.key_count;
+        self.memory += other.memory;
+        self.memory_sum_of_squares += other.memory_sum_of_squares;
+        self.ttl_count += other.ttl_count;
+        self.with_ttl_count += other.with_ttl_count;
+        for (segment, child) in other.children {
+            self.child(&segment).merge(child);
+        }
+    }
+
+    fn child(&mut self, segment: &str) -> &mut PrefixNode {
+        let segment = if self.children.len() >= MAX_CHILDREN && !self.children.contains_key(segment)
+        {
+            OTHER_SEGMENT
+        } else {
+            segment
+        };
+        self.children.entry(segment.to_string()).or_default()
+    }
+}
+
+// Splits bytes on every occurrence of a (possibly multi-byte) delimiter
+fn split<'a>(key: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut idx = 0;
+    while idx + delimiter.len() <= key.len() {
+        if key[idx..].starts_with(delimiter) {
+            segments.push(&key[start..idx]);
+            idx += delimiter.len();
+            start = idx;
+        } else {
+            idx += 1;
+        }
+    }
+    segments.push(&key[start..]);
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::sample::SampleValue::*;
+
+    #[test]
+    fn prefix_tree_works() {
+        let mut tree = PrefixTree::new(':', 2, KeyEscape::Hex);
+        let mut other = PrefixTree::new(':', 2, KeyEscape::Hex);
+
+        for (key, memory, ttl_ms) in [
+            (&b"user:1:sessions"[..], 10, -1),
+            (b"user:2:sessions", 20, 5000),
+            (b"user:2:friends", 30, -1),
+            (b"lock", 40, 1000),
+            (b"\xff\x01:1", 50, -1),
+        ] {
+            let sample = Sample {
+                exists: Unsampled,
+                memory: Sampled(memory),
+                ttl_ms: Sampled(ttl_ms),
+                expires_at: Unsampled,
+                type_: Unsampled,
+                count: Unsampled,
+                encoding: Unsampled,
+                idle: Unsampled,
+                freq: Unsampled,
+            };
+            let tree = if key.starts_with(b"user") {
+                &mut tree
+            } else {
+                &mut other
+            };
+            tree.add(key, &sample);
+        }
+
+        tree.merge(other);
+        assert_eq!(tree.root.key_count, 5);
+        assert_eq!(tree.root.memory, 150);
+        assert_eq!(tree.root.memory_sum_of_squares, 5500.0);
+        assert_eq!(tree.root.with_ttl_count, 2);
+
+        let user = &tree.root.children["user"];
+        assert_eq!(user.key_count, 3);
+        assert_eq!(user.memory, 60);
+        assert_eq!(user.ttl_count, 3);
+        assert_eq!(user.with_ttl_count, 1);
+        assert_eq!(user.children.len(), 1);
+        // Deeper than --tree-depth
+        assert!(user.children["*"].children.is_empty());
+        assert_eq!(tree.root.children["lock"].memory, 40);
+        assert_eq!(tree.root.children[r"\xff\x01"].children["*"].memory, 50);
+
+        assert_eq!(split(b"a::b::", b"::"), vec![&b"a"[..], b"b", b""]);
+
+        // Past MAX_CHILDREN, new segments are lumped together
+        let mut node = PrefixNode::default();
+        for idx in 0..=MAX_CHILDREN {
+            node.child(&format!("k{}", idx)).key_count += 1;
+        }
+        node.child("k0").key_count += 1;
+        assert_eq!(node.children.len(), MAX_CHILDREN + 1);
+        assert_eq!(node.children["k0"].key_count, 2);
+        assert_eq!(node.children[OTHER_SEGMENT].key_count, 1);
+    }
+}
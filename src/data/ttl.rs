@@ -1,35 +1,37 @@
-use crate::data::math::percentile_of_sorted;
-use crate::data::{Data, Keys};
+use std::time::SystemTime;
 
-pub fn pct_with_ttl(data: &Data, keys: &Keys) -> f64 {
-    let values = ttl_values(data, keys);
+use crate::data::bin::Bin;
+use crate::data::math::Bucket;
 
-    if values.is_empty() {
+// TTLs are sampled in milliseconds, but reported in (fractional) seconds
+
+pub fn pct_with_ttl(bin: &Bin) -> f64 {
+    if bin.ttl_count == 0 {
         0.0
     } else {
-        let with_ttl_count = values.iter().filter(|&&v| v >= 0).count();
-        (with_ttl_count as f64) / (values.len() as f64) * 100.0
+        (bin.ttl_ms.len() as f64) / (bin.ttl_count as f64) * 100.0
     }
 }
 
-pub fn percentile(data: &Data, keys: &Keys, pct: f64) -> f64 {
-    let mut values = ttl_values(data, keys)
-        .iter()
-        .filter(|&&v| v >= 0)
-        .map(|&v| v as f64)
-        .collect::<Vec<f64>>();
-
-    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-    if values.is_empty() {
-        0.0
-    } else {
-        percentile_of_sorted(&values, pct).round()
-    }
+// Rounded to the nearest millisecond
+pub fn percentile(bin: &Bin, pct: f64) -> f64 {
+    bin.ttl_ms.percentile(pct).round() / 1000.0
 }
 
-fn ttl_values(data: &Data, keys: &Keys) -> Vec<i64> {
-    keys.iter()
-        .map(|k| data.get_sample(k).unwrap().ttl())
+// Only keys that have a TTL are included
+pub fn histogram(bin: &Bin, n_buckets: usize) -> Vec<Bucket> {
+    bin.ttl_ms
+        .histogram(n_buckets)
+        .into_iter()
+        .map(|bucket| Bucket {
+            lo: bucket.lo / 1000.0,
+            hi: bucket.hi / 1000.0,
+            count: bucket.count,
+        })
         .collect()
 }
+
+// The earliest and latest absolute expiry times of keys that have a TTL, if any do
+pub fn expiry_range(bin: &Bin) -> Option<(SystemTime, SystemTime)> {
+    bin.expiry_range
+}
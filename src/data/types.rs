@@ -1,11 +1,14 @@
 use std::cmp::Reverse;
-use std::collections::HashMap;
 
-use crate::data::{Data, Keys};
+use crate::data::bin::Bin;
 
-pub fn type_pcts(data: &Data, keys: &Keys) -> Vec<(String, f64)> {
-    let type_counts = type_counts(data, keys);
-    let total_count = keys.len();
+// The core data types that TYPE can return. Modules can add their own types, too (like
+// "ReJSON-RL"). https://redis.io/commands/type
+pub const REDIS_TYPES: [&str; 6] = ["string", "list", "set", "zset", "hash", "stream"];
+
+pub fn type_pcts(bin: &Bin) -> Vec<(String, f64)> {
+    let type_counts = type_counts(bin);
+    let total_count = bin.key_count;
 
     let mut type_pcts = Vec::new();
 
@@ -17,15 +20,8 @@ pub fn type_pcts(data: &Data, keys: &Keys) -> Vec<(String, f64)> {
     type_pcts
 }
 
-fn type_counts(data: &Data, keys: &Keys) -> Vec<(String, usize)> {
-    let mut counts = HashMap::new();
-
-    for t in keys.iter().map(|k| data.get_sample(k).unwrap().type_()) {
-        let count = counts.entry(t).or_insert(0);
-        *count += 1;
-    }
-
-    let mut sorted_counts: Vec<_> = counts.into_iter().collect();
+fn type_counts(bin: &Bin) -> Vec<(String, u64)> {
+    let mut sorted_counts: Vec<_> = bin.type_counts.clone().into_iter().collect();
     sorted_counts.sort_by_key(|(_type, count)| Reverse(*count));
     sorted_counts
 }
@@ -39,31 +35,25 @@ mod tests {
         use crate::sampling::sample::Sample;
         use crate::sampling::sample::SampleValue::*;
 
-        let mut samples = HashMap::new();
-        let mut keys = Vec::new();
+        let mut bin = Bin::new(0);
 
         for (idx, t) in ["set", "string", "string", "string"].iter().enumerate() {
-            let key = format!("key_{}", idx);
-            keys.push(key.clone());
-            samples.insert(
-                key,
-                Sample {
-                    exists: Unsampled,
-                    memory: Unsampled,
-                    ttl: Unsampled,
-                    type_: Sampled(t.to_string()),
-                },
-            );
+            let sample = Sample {
+                exists: Unsampled,
+                memory: Unsampled,
+                ttl_ms: Unsampled,
+                expires_at: Unsampled,
+                type_: Sampled(t.to_string()),
+                count: Unsampled,
+                encoding: Unsampled,
+                idle: Unsampled,
+                freq: Unsampled,
+            };
+            bin.add(&format!("key_{}", idx).into_bytes(), &sample);
         }
 
-        let data = Data {
-            samples,
-            bins: Default::default(),
-            patterns: vec![],
-        };
-
         assert_eq!(
-            type_pcts(&data, &keys),
+            type_pcts(&bin),
             vec![("string".to_string(), 75.0), ("set".to_string(), 25.0),]
         );
     }
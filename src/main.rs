@@ -1,5 +1,11 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::CommandFactory;
 
+use crate::data::pattern::KeyPattern;
+use crate::data::{BinMode, Data};
+use crate::metadata::{MatchCounts, Metadata};
+
+mod cluster;
 mod config;
 mod data;
 mod metadata;
@@ -10,9 +16,73 @@ mod stats;
 
 fn main() {
     // Parse CLI args into a Config struct
-    let mut config = config::Config::parse();
+    let mut config = config::Config::load();
     config.normalize();
 
+    // The prefix tree, bins named after what named captures matched, and discovered patterns all
+    // show parts of keys that --redact would have to hide
+    if config.redact.is_some() {
+        let conflict = if config.output_mode == output::OutputMode::Tree {
+            Some("-o tree")
+        } else if config.auto_patterns {
+            Some("--auto-patterns")
+        } else if config
+            .patterns
+            .iter()
+            .any(|def| def.pattern.has_named_captures())
+        {
+            Some("patterns with named captures")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            config::Config::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--redact can't be used with {}", conflict),
+                )
+                .exit();
+        }
+    }
+
+    // Per-node output is printed before the merged output, which only works for the human-readable
+    // outputs (the rest would have several documents on STDOUT)
+    if config.per_node
+        && !matches!(
+            config.output_mode,
+            output::OutputMode::Table | output::OutputMode::Tree
+        )
+    {
+        config::Config::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--per-node can only be used with -o table or -o tree",
+            )
+            .exit();
+    }
+
+    // The Lua script only draws random keys, so it has nothing to do with a full scan
+    if config.sample_mode == sampling::SampleMode::All
+        && config.sample_backend == sampling::SampleBackend::Lua
+    {
+        config::Config::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--backend lua can only be used with --sample random",
+            )
+            .exit();
+    }
+
+    // Raw records on STDOUT would be mixed into the output, unless there isn't any
+    if config.raw_path.as_deref() == Some("-") && config.output_mode != output::OutputMode::Silent {
+        config::Config::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--raw - can only be used with -o none",
+            )
+            .exit();
+    }
+
     // Connect to Redis
     let mut conn = redis_connection(config.url.clone()).unwrap();
 
@@ -23,20 +93,113 @@ fn main() {
         }
     }
 
-    // Get metadata and sample data from Redis
-    let metadata = metadata::get_metadata(&mut conn);
-    let data = sampling::collect_samples(&config, &mut conn);
+    // Only one of the idle/freq stats can be collected, depending on the maxmemory-policy
+    if config.has_stat(&stats::Stats::Idle) || config.has_stat(&stats::Stats::Freq) {
+        let policy = metadata::get_maxmemory_policy(&mut conn).unwrap();
+        config.choose_access_stat(&policy);
+
+        // Counting elements would reset the idle time (or bump the access frequency) being measured
+        // without CLIENT NO-TOUCH, so on older Redis, counts are skipped instead
+        if config.has_stat(&stats::Stats::Count) {
+            if let Err(e) = sampling::no_touch(&mut conn) {
+                eprintln!(
+                    "Can't count elements without touching keys ({}), so skipping the count stat",
+                    e
+                );
+                config.stats.retain(|s| *s != stats::Stats::Count);
+            }
+        }
+    }
+
+    // Optionally discover patterns from a batch of random keys, and print them so they can be
+    // reused
+    if config.auto_patterns {
+        let patterns = sampling::discover::discover_patterns(&config, &mut conn).unwrap();
+        let quoted: Vec<String> = patterns
+            .iter()
+            .map(|p| format!("'{}'", p.as_str().replace('\'', "'\\''")))
+            .collect();
+        eprintln!("Discovered patterns: {}", quoted.join(" "));
+        config.add_patterns(
+            patterns
+                .into_iter()
+                .map(|p| KeyPattern::from(p).into())
+                .collect(),
+        );
+    }
+
+    // Optionally write every sample to a file as it's collected
+    let raw = output::raw::open(&config).unwrap();
+
+    // Get metadata and sample data from Redis. For a Redis Cluster, every primary is sampled, and
+    // the results are merged together.
+    let (metadata, data) = if cluster::is_cluster(&mut conn) {
+        let mut metadata = Metadata::cluster();
+        let mut data = Data::new(&config);
+
+        for (addr, node_metadata, node_data) in
+            cluster::collect_samples(&config, &mut conn, raw.clone()).unwrap()
+        {
+            if config.per_node {
+                eprintln!("Node {}:", addr);
+                print_summary(&config, &node_metadata, &node_data);
+                output::output(&config, &node_metadata, &node_data);
+            }
+
+            data.merge(node_data);
+            metadata.add_node(addr, node_metadata);
+        }
+
+        (metadata, data)
+    } else {
+        let mut metadata = metadata::get_metadata(&mut conn);
+        let data = sampling::collect_samples(&config, &mut conn, &mut metadata, raw.clone());
+        (metadata, data)
+    };
+
+    if let Some(raw) = raw {
+        raw.borrow_mut().flush().unwrap();
+    }
 
     // Display stats
-    eprintln!(
-        "Sampled {} of {} keys in db{}",
-        data.sample_count(),
-        metadata.total_keys,
-        metadata.redis_db,
-    );
+    print_summary(&config, &metadata, &data);
     output::output(&config, &metadata, &data);
 }
 
+fn print_summary(config: &config::Config, metadata: &Metadata, data: &Data) {
+    if metadata.nodes.is_empty() {
+        eprintln!(
+            "Sampled {} of {} keys in db{}",
+            data.sample_count(),
+            metadata.total_keys,
+            metadata.redis_db,
+        );
+    } else {
+        eprintln!(
+            "Sampled {} of {} keys across {} cluster primaries",
+            data.sample_count(),
+            metadata.total_keys,
+            metadata.nodes.len(),
+        );
+    }
+
+    match &metadata.match_counts {
+        Some(MatchCounts {
+            examined: Some(examined),
+            matched,
+        }) => eprintln!("Matched {} of {} keys examined", matched, examined),
+        Some(MatchCounts {
+            examined: None,
+            matched,
+        }) => eprintln!("Matched {} keys", matched),
+        None => {}
+    }
+
+    if config.bin_mode == BinMode::AllMatches {
+        eprintln!("Keys are counted in every pattern they match, so bins may overlap");
+    }
+}
+
 fn redis_connection(url: String) -> redis::RedisResult<redis::Connection> {
     let client = redis::Client::open(url)?;
     client.get_connection()
@@ -47,18 +210,47 @@ mod tests {
     // This doesn't test anything, it's just a helper function that returns a basic config and
     // Redis connection for use in other tests.
     pub fn test_config_and_conn() -> (crate::config::Config, redis::Connection) {
-        let config = crate::config::Config {
+        let mut config = crate::config::Config {
+            config_path: None,
+            profile: None,
             sample_mode: crate::sampling::SampleMode::Random,
+            sample_backend: crate::sampling::SampleBackend::Pipeline,
             n_samples: 1,
+            match_patterns: vec![],
+            exclude_patterns: vec![],
+            max_draws: 100,
+            target_error_pct: None,
             batch_size: 1,
             batch_sleep_ms: 0,
+            max_latency_ms: None,
+            max_server_ops: None,
+            max_server_cpu_pct: None,
             stats: crate::stats::Stats::all(),
+            idle_threshold: std::time::Duration::from_secs(60),
+            top: 0,
             output_mode: crate::output::OutputMode::Table,
+            delimiter: ':',
+            tree_depth: 3,
+            tree_min_pct: 1,
+            raw_path: None,
+            raw_format: crate::output::raw::RawFormat::Csv,
+            key_escape: crate::data::escape::KeyEscape::Hex,
+            redact: None,
+            redact_key: None,
+            no_example_keys: false,
             url: "redis://127.0.0.1".to_string(),
+            per_node: false,
+            auto_patterns: false,
+            bin_mode: crate::data::BinMode::FirstMatch,
+            labels: vec![],
             patterns: vec![],
         };
 
-        let conn = crate::redis_connection(config.url.clone()).unwrap();
+        let mut conn = crate::redis_connection(config.url.clone()).unwrap();
+
+        // Redis can only give us one of these, depending on its maxmemory-policy
+        let policy = crate::metadata::get_maxmemory_policy(&mut conn).unwrap();
+        config.choose_access_stat(&policy);
 
         (config, conn)
     }
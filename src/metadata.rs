@@ -1,21 +1,148 @@
+use std::time::SystemTime;
+
 use redis::{Connection, ConnectionLike};
 use regex::Regex;
 
 pub struct Metadata {
     pub redis_db: i64,
+    pub redis_version: String,
+    // When we started collecting data from Redis
+    pub timestamp: SystemTime,
     pub total_keys: u64,
+    pub match_counts: Option<MatchCounts>,
+    // For a Redis Cluster, this is the metadata of each primary (keyed by its address), and the
+    // other fields are the sum of them
+    pub nodes: Vec<(String, Metadata)>,
+}
+
+// When sampling is restricted to keys matching --match patterns, this records how many keys the
+// sampler looked at versus how many of them actually matched. With --sample=all, Redis doesn't tell
+// us how many keys SCAN looked at to produce its MATCH results, so `examined` is None (unless SCAN
+// was run without MATCH); the scan covers the whole keyspace, so every matching key is counted in
+// `matched`.
+#[derive(Clone, Copy)]
+pub struct MatchCounts {
+    pub examined: Option<u64>,
+    pub matched: u64,
+}
+
+impl Metadata {
+    // Returns an empty Metadata that cluster nodes can be added to
+    pub fn cluster() -> Metadata {
+        Metadata {
+            redis_db: 0,
+            redis_version: String::new(),
+            timestamp: SystemTime::now(),
+            total_keys: 0,
+            match_counts: None,
+            nodes: vec![],
+        }
+    }
+
+    pub fn add_node(&mut self, addr: String, node: Metadata) {
+        self.total_keys += node.total_keys;
+
+        // Nodes should all be running the same version, but this is just informational anyways
+        if self.redis_version.is_empty() {
+            self.redis_version = node.redis_version.clone();
+        }
+
+        if let Some(node_counts) = &node.match_counts {
+            let counts = self.match_counts.get_or_insert(MatchCounts {
+                examined: Some(0),
+                matched: 0,
+            });
+            counts.examined = counts
+                .examined
+                .zip(node_counts.examined)
+                .map(|(a, b)| a + b);
+            counts.matched += node_counts.matched;
+        }
+
+        self.nodes.push((addr, node));
+    }
+
+    // The number of keys that the sample is drawn from. This is the whole keyspace, unless sampling
+    // was restricted with --match, in which case it's the (possibly estimated) number of matching
+    // keys.
+    pub fn population(&self) -> u64 {
+        // Each node's population is estimated separately, since they're sampled separately
+        if !self.nodes.is_empty() {
+            return self
+                .nodes
+                .iter()
+                .map(|(_addr, node)| node.population())
+                .sum();
+        }
+
+        match &self.match_counts {
+            Some(MatchCounts {
+                examined: Some(0), ..
+            }) => 0,
+            Some(MatchCounts {
+                examined: Some(examined),
+                matched,
+            }) => {
+                let pct_matched = *matched as f64 / *examined as f64;
+                (self.total_keys as f64 * pct_matched).round() as u64
+            }
+            Some(MatchCounts {
+                examined: None,
+                matched,
+            }) => *matched,
+            None => self.total_keys,
+        }
+    }
 }
 
 pub fn get_metadata(conn: &mut Connection) -> Metadata {
+    let timestamp = SystemTime::now();
     let redis_db = conn.get_db();
+    let redis_version = get_redis_version(conn).unwrap();
     let total_keys = get_total_keys(conn).unwrap();
 
     Metadata {
         redis_db,
+        redis_version,
+        timestamp,
         total_keys,
+        match_counts: None,
+        nodes: vec![],
     }
 }
 
+// Gets the maxmemory-policy with CONFIG GET, falling back to the "memory" section of INFO since
+// CONFIG is often disabled on hosted Redis.
+// https://redis.io/commands/config-get
+pub fn get_maxmemory_policy(conn: &mut Connection) -> Result<String, String> {
+    let config: Result<(String, String), _> = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("maxmemory-policy")
+        .query(conn);
+    if let Ok((_name, policy)) = config {
+        return Ok(policy);
+    }
+
+    let res: redis::InfoDict = redis::cmd("INFO")
+        .arg("memory")
+        .query(conn)
+        .map_err(|e| e.to_string())?;
+
+    res.get("maxmemory_policy")
+        .ok_or_else(|| "maxmemory_policy not found".to_string())
+}
+
+fn get_redis_version(conn: &mut Connection) -> Result<String, String> {
+    // See the "server" section within: https://redis.io/commands/info
+    let res: redis::InfoDict = redis::cmd("INFO")
+        .arg("server")
+        .query(conn)
+        .map_err(|e| e.to_string())?;
+
+    res.get("redis_version")
+        .ok_or_else(|| "redis_version not found".to_string())
+}
+
 fn get_total_keys(conn: &mut Connection) -> Result<u64, String> {
     // The current logical Redis database
     let db = conn.get_db();
@@ -27,8 +154,12 @@ fn get_total_keys(conn: &mut Connection) -> Result<u64, String> {
         .query(conn)
         .map_err(|e| e.to_string())?;
 
-    // This will be a String like: "keys=321,expires=123,avg_ttl=456"
-    let db_info: String = res.get(&db_key).ok_or(format!("{} not found", db_key))?;
+    // This will be a String like: "keys=321,expires=123,avg_ttl=456". Redis leaves out databases
+    // that don't have any keys, which is common for cluster nodes that were just added.
+    let db_info: String = match res.get(&db_key) {
+        Some(db_info) => db_info,
+        None => return Ok(0),
+    };
 
     // Extract and parse the "keys" value
     Regex::new(r"keys=(?P<keys>\d+)")
@@ -58,4 +189,58 @@ mod tests {
         assert!(keys.is_ok());
         assert!(keys.unwrap() >= 10);
     }
+
+    #[test]
+    fn population_works() {
+        let mut metadata = Metadata {
+            redis_db: 0,
+            redis_version: "7.2.0".to_string(),
+            timestamp: SystemTime::now(),
+            total_keys: 1000,
+            match_counts: None,
+            nodes: vec![],
+        };
+        assert_eq!(metadata.population(), 1000);
+
+        metadata.match_counts = Some(MatchCounts {
+            examined: Some(500),
+            matched: 42,
+        });
+        assert_eq!(metadata.population(), 84);
+
+        metadata.match_counts = Some(MatchCounts {
+            examined: Some(0),
+            matched: 0,
+        });
+        assert_eq!(metadata.population(), 0);
+
+        // A full scan counts every matching key, whatever the keyspace's size
+        metadata.match_counts = Some(MatchCounts {
+            examined: None,
+            matched: 42,
+        });
+        assert_eq!(metadata.population(), 42);
+
+        // Each node's population is estimated from its own match rate, and a node that wasn't
+        // sampled at all adds nothing
+        let mut cluster = Metadata::cluster();
+        for (total_keys, examined, matched) in [(1000, 100, 10), (3000, 200, 40), (5, 0, 0)] {
+            let node = Metadata {
+                redis_db: 0,
+                redis_version: "7.2.0".to_string(),
+                timestamp: SystemTime::now(),
+                total_keys,
+                match_counts: Some(MatchCounts {
+                    examined: Some(examined),
+                    matched,
+                }),
+                nodes: vec![],
+            };
+            cluster.add_node("127.0.0.1:30001".to_string(), node);
+        }
+        assert_eq!(cluster.total_keys, 4005);
+        assert_eq!(cluster.population(), 100 + 600);
+        let counts = cluster.match_counts.as_ref().unwrap();
+        assert_eq!((counts.examined, counts.matched), (Some(300), 50));
+    }
 }
@@ -2,18 +2,36 @@ use crate::config::Config;
 use crate::data::Data;
 use crate::metadata::Metadata;
 
+mod delimited;
+mod html;
+mod json;
+pub mod raw;
 mod table;
+mod tree;
 
 #[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
 pub enum OutputMode {
     Table,
+    Csv,
+    Tsv,
+    Json,
+    Html,
+    Tree,
+    // Only the summary on STDERR, for when --raw records are all that's wanted on STDOUT
+    #[value(name = "none")]
+    Silent,
 }
 
-// Eventually, there will be more OutputMode options, and this will make more sense
 pub fn output(config: &Config, metadata: &Metadata, data: &Data) {
     use OutputMode::*;
 
     match config.output_mode {
         Table => table::table(config, metadata, data),
+        Csv => delimited::delimited(config, metadata, data, b','),
+        Tsv => delimited::delimited(config, metadata, data, b'\t'),
+        Json => json::json(config, metadata, data),
+        Html => html::html(config, metadata, data),
+        Tree => tree::tree(config, metadata, data),
+        Silent => {}
     }
 }
@@ -0,0 +1,216 @@
+use std::io;
+
+use crate::config::Config;
+use crate::data::{count, encodings, freq, idle, keys, memory, ttl, types, Data};
+use crate::metadata::Metadata;
+use crate::stats::Stats;
+
+// Writes one row per pattern, with one column per metric. Unlike the table output, values are raw
+// numbers (bytes, seconds, and percentages) so they can be loaded straight into a spreadsheet or
+// database. The columns only depend on which --stats were requested (and whether any patterns have
+// named captures or labels), so they're stable from run to run; in particular, there's a column for
+// every Redis data type (and encoding), even ones that weren't sampled.
+pub fn delimited(config: &Config, metadata: &Metadata, data: &Data, delimiter: u8) {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(io::stdout());
+
+    /***************/
+    /* ADD HEADERS */
+    /***************/
+
+    let mut headers = vec!["pattern"];
+
+    if config.has_named_captures() {
+        headers.push("bin");
+    }
+
+    if config.has_labels() {
+        headers.push("label");
+    }
+
+    headers.extend(["keys_counted", "keys_est_total", "keys_est_total_margin"]);
+
+    if config.has_stat(&Stats::Memory) {
+        headers.extend([
+            "memory_sum_bytes",
+            "memory_est_total_bytes",
+            "memory_est_total_margin_bytes",
+            "memory_p50_bytes",
+            "memory_p90_bytes",
+            "memory_p99_bytes",
+        ]);
+    }
+
+    if config.has_stat(&Stats::Ttl) {
+        headers.extend([
+            "ttl_pct",
+            "ttl_p50_seconds",
+            "ttl_p90_seconds",
+            "ttl_p99_seconds",
+            "ttl_earliest_expiry",
+            "ttl_latest_expiry",
+        ]);
+    }
+
+    let type_headers: Vec<String> = types::REDIS_TYPES
+        .iter()
+        .chain(["other"].iter())
+        .map(|type_| format!("type_{}_pct", type_))
+        .collect();
+    if config.has_stat(&Stats::Type) {
+        headers.extend(type_headers.iter().map(|h| h.as_str()));
+    }
+
+    if config.has_stat(&Stats::Count) {
+        headers.extend([
+            "count_sum",
+            "count_est_total",
+            "count_est_total_margin",
+            "count_p50",
+            "count_p90",
+            "count_p99",
+        ]);
+    }
+
+    let encoding_headers: Vec<String> = encodings::REDIS_ENCODINGS
+        .iter()
+        .chain(["other"].iter())
+        .map(|encoding| format!("encoding_{}_pct", encoding))
+        .collect();
+    if config.has_stat(&Stats::Encoding) {
+        headers.extend(encoding_headers.iter().map(|h| h.as_str()));
+    }
+
+    if config.has_stat(&Stats::Idle) {
+        headers.extend([
+            "idle_over_threshold_pct",
+            "idle_p50_seconds",
+            "idle_p90_seconds",
+            "idle_p99_seconds",
+        ]);
+    }
+
+    if config.has_stat(&Stats::Freq) {
+        headers.extend(["freq_p50", "freq_p90", "freq_p99"]);
+    }
+
+    writer.write_record(&headers).unwrap();
+
+    /************/
+    /* ADD ROWS */
+    /************/
+
+    for (bin_key, bin) in data.bins() {
+        let mut row = vec![config.patterns[bin_key.idx].pattern.as_str()];
+
+        if config.has_named_captures() {
+            row.push(bin_key.name.clone());
+        }
+
+        if config.has_labels() {
+            row.push(bin_key.label.clone().unwrap_or_default());
+        }
+
+        row.extend([
+            bin.key_count.to_string(),
+            keys::total_estimate(metadata, data, bin).to_string(),
+            margin(keys::total_estimate_margin(metadata, data, bin)),
+        ]);
+
+        if config.has_stat(&Stats::Memory) {
+            row.extend([
+                memory::total(bin).to_string(),
+                memory::total_estimate(metadata, data, bin).to_string(),
+                margin(memory::total_estimate_margin(metadata, data, bin)),
+                format!("{:.2}", memory::percentile(bin, 50_f64)),
+                format!("{:.2}", memory::percentile(bin, 90_f64)),
+                format!("{:.2}", memory::percentile(bin, 99_f64)),
+            ]);
+        }
+
+        if config.has_stat(&Stats::Ttl) {
+            row.extend([
+                format!("{:.2}", ttl::pct_with_ttl(bin)),
+                format!("{:.3}", ttl::percentile(bin, 50_f64)),
+                format!("{:.3}", ttl::percentile(bin, 90_f64)),
+                format!("{:.3}", ttl::percentile(bin, 99_f64)),
+            ]);
+
+            // Absolute times are in RFC 3339 format, and left empty if no keys have a TTL
+            let expiry_range = ttl::expiry_range(bin);
+            for expires_at in [expiry_range.map(|r| r.0), expiry_range.map(|r| r.1)] {
+                row.push(
+                    expires_at
+                        .map(|t| humantime::format_rfc3339_millis(t).to_string())
+                        .unwrap_or_default(),
+                );
+            }
+        }
+
+        if config.has_stat(&Stats::Type) {
+            row.extend(known_pcts(&types::type_pcts(bin), &types::REDIS_TYPES));
+        }
+
+        if config.has_stat(&Stats::Count) {
+            row.extend([
+                count::total(bin).to_string(),
+                count::total_estimate(metadata, data, bin).to_string(),
+                margin(count::total_estimate_margin(metadata, data, bin)),
+                format!("{:.2}", count::percentile(bin, 50_f64)),
+                format!("{:.2}", count::percentile(bin, 90_f64)),
+                format!("{:.2}", count::percentile(bin, 99_f64)),
+            ]);
+        }
+
+        if config.has_stat(&Stats::Encoding) {
+            row.extend(known_pcts(
+                &encodings::encoding_pcts(bin),
+                &encodings::REDIS_ENCODINGS,
+            ));
+        }
+
+        if config.has_stat(&Stats::Idle) {
+            row.extend([
+                format!("{:.2}", idle::pct_idle_over(bin, config.idle_threshold)),
+                idle::percentile(bin, 50_f64).to_string(),
+                idle::percentile(bin, 90_f64).to_string(),
+                idle::percentile(bin, 99_f64).to_string(),
+            ]);
+        }
+
+        if config.has_stat(&Stats::Freq) {
+            row.extend([
+                format!("{:.2}", freq::percentile(bin, 50_f64)),
+                format!("{:.2}", freq::percentile(bin, 90_f64)),
+                format!("{:.2}", freq::percentile(bin, 99_f64)),
+            ]);
+        }
+
+        writer.write_record(&row).unwrap();
+    }
+
+    writer.flush().unwrap();
+}
+
+// Lays out percentages (like type_pcts) as one value for each of the `known` names, in that order,
+// followed by the total for every other name
+fn known_pcts(pcts: &[(String, f64)], known: &[&str]) -> Vec<String> {
+    let pct_where = |is_match: &dyn Fn(&str) -> bool| -> f64 {
+        pcts.iter()
+            .filter(|(name, _pct)| is_match(name))
+            .fold(0_f64, |sum, (_name, pct)| sum + pct)
+    };
+
+    let mut values: Vec<String> = known
+        .iter()
+        .map(|&k| format!("{:.2}", pct_where(&|name| name == k)))
+        .collect();
+    values.push(format!("{:.2}", pct_where(&|name| !known.contains(&name))));
+    values
+}
+
+// Margins of error are left empty if there were too few samples to compute one
+fn margin(margin: Option<u64>) -> String {
+    margin.map(|m| m.to_string()).unwrap_or_default()
+}
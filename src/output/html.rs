@@ -0,0 +1,312 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use humantime::{format_duration, format_rfc3339_seconds};
+use pretty_bytes::converter::convert;
+
+use crate::config::Config;
+use crate::data::math::Bucket;
+use crate::data::{count, encodings, freq, idle, memory, ttl, types, Data};
+use crate::metadata::Metadata;
+use crate::output::table;
+use crate::stats::Stats;
+
+const HISTOGRAM_BUCKETS: usize = 20;
+const SVG_WIDTH: f64 = 400.0;
+const SVG_HEIGHT: f64 = 120.0;
+
+const STYLE: &str = "
+body { font-family: -apple-system, BlinkMacSystemFont, sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.4em 0.8em; text-align: left; vertical-align: top; }
+th { background: #f4f4f4; }
+td { font-family: monospace; white-space: pre; }
+dt { font-weight: bold; float: left; clear: left; width: 10em; }
+dd { margin-left: 11em; }
+section { border-top: 1px solid #ccc; padding-top: 1em; }
+.charts { display: flex; flex-wrap: wrap; gap: 2em; }
+.bar { fill: #4a7ebb; }
+.axis { font-size: 11px; fill: #555; }
+";
+
+// Prints a self-contained HTML report (no external scripts, styles, or images) that's easy to
+// attach to a ticket. It has the run metadata, the same summary table as the table output, and for
+// each bin, histograms of memory usage and TTLs along with its data type breakdown.
+pub fn html(config: &Config, metadata: &Metadata, data: &Data) {
+    let mut html = String::new();
+
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html lang=\"en\">").unwrap();
+    writeln!(html, "<head>").unwrap();
+    writeln!(html, "<meta charset=\"utf-8\">").unwrap();
+    writeln!(html, "<title>Redis keyspace stats</title>").unwrap();
+    writeln!(html, "<style>{}</style>", STYLE).unwrap();
+    writeln!(html, "</head>").unwrap();
+    writeln!(html, "<body>").unwrap();
+    writeln!(html, "<h1>Redis keyspace stats</h1>").unwrap();
+
+    /************/
+    /* METADATA */
+    /************/
+
+    writeln!(html, "<dl>").unwrap();
+    let mut metadata_items = vec![
+        (
+            "Collected at",
+            humantime::format_rfc3339_seconds(metadata.timestamp).to_string(),
+        ),
+        ("Redis version", metadata.redis_version.clone()),
+        ("Total keys", metadata.total_keys.to_string()),
+        ("Sampled keys", data.sample_count().to_string()),
+        (
+            "Sample mode",
+            config
+                .sample_mode
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+        ),
+        (
+            "Bin mode",
+            config
+                .bin_mode
+                .to_possible_value()
+                .unwrap()
+                .get_name()
+                .to_string(),
+        ),
+    ];
+    if metadata.nodes.is_empty() {
+        metadata_items.push(("Database", format!("db{}", metadata.redis_db)));
+    } else {
+        let addrs: Vec<&str> = metadata.nodes.iter().map(|(a, _)| a.as_str()).collect();
+        metadata_items.push(("Cluster primaries", addrs.join(", ")));
+    }
+    if let Some(counts) = &metadata.match_counts {
+        metadata_items.push((
+            "Matched keys",
+            match counts.examined {
+                Some(examined) => format!("{} of {} examined", counts.matched, examined),
+                None => counts.matched.to_string(),
+            },
+        ));
+    }
+    for (name, value) in metadata_items {
+        writeln!(html, "<dt>{}</dt><dd>{}</dd>", name, escape(&value)).unwrap();
+    }
+    writeln!(html, "</dl>").unwrap();
+
+    /*****************/
+    /* SUMMARY TABLE */
+    /*****************/
+
+    writeln!(html, "<h2>Summary</h2>").unwrap();
+    writeln!(html, "<table>").unwrap();
+    write!(html, "<tr>").unwrap();
+    for header in table::headers(config) {
+        write!(html, "<th>{}</th>", escape(header)).unwrap();
+    }
+    writeln!(html, "</tr>").unwrap();
+    for (bin_key, bin) in data.bins() {
+        write!(html, "<tr>").unwrap();
+        for cell in table::cells(config, metadata, data, bin_key.display_name(), bin) {
+            write!(html, "<td>{}</td>", escape(&cell)).unwrap();
+        }
+        writeln!(html, "</tr>").unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    /**********************/
+    /* PER-BIN BREAKDOWNS */
+    /**********************/
+
+    for (bin_key, bin) in data.bins() {
+        writeln!(html, "<section>").unwrap();
+        writeln!(html, "<h2>{}</h2>", escape(bin_key.display_name())).unwrap();
+        writeln!(html, "<div class=\"charts\">").unwrap();
+
+        if config.has_stat(&Stats::Memory) {
+            let buckets = memory::histogram(bin, HISTOGRAM_BUCKETS);
+            let svg = histogram_svg(&buckets, convert);
+            writeln!(html, "<div><h3>Memory</h3>{}</div>", svg).unwrap();
+        }
+
+        if config.has_stat(&Stats::Ttl) {
+            let buckets = ttl::histogram(bin, HISTOGRAM_BUCKETS);
+            let svg = histogram_svg(&buckets, |v| {
+                format_duration(Duration::from_millis((v * 1000.0).round() as u64)).to_string()
+            });
+            let expiry_range = match ttl::expiry_range(bin) {
+                Some((earliest, latest)) => format!(
+                    "<p>Expiring from {} to {}</p>",
+                    format_rfc3339_seconds(earliest),
+                    format_rfc3339_seconds(latest)
+                ),
+                None => String::new(),
+            };
+            writeln!(
+                html,
+                "<div><h3>TTL ({:.2}% have TTL)</h3>{}{}</div>",
+                ttl::pct_with_ttl(bin),
+                svg,
+                expiry_range
+            )
+            .unwrap();
+        }
+
+        if config.has_stat(&Stats::Type) {
+            writeln!(html, "<div><h3>Type</h3><table>").unwrap();
+            for (type_, pct) in types::type_pcts(bin) {
+                writeln!(
+                    html,
+                    "<tr><th>{}</th><td>{:.2}%</td></tr>",
+                    escape(&type_),
+                    pct
+                )
+                .unwrap();
+            }
+            writeln!(html, "</table></div>").unwrap();
+        }
+
+        if config.has_stat(&Stats::Count) {
+            let buckets = count::histogram(bin, HISTOGRAM_BUCKETS);
+            let svg = histogram_svg(&buckets, |v| format!("{:.0}", v));
+            writeln!(html, "<div><h3>Count</h3>{}</div>", svg).unwrap();
+        }
+
+        if config.has_stat(&Stats::Idle) {
+            let buckets = idle::histogram(bin, HISTOGRAM_BUCKETS);
+            let svg = histogram_svg(&buckets, |v| {
+                format_duration(Duration::from_secs(v.round() as u64)).to_string()
+            });
+            writeln!(
+                html,
+                "<div><h3>Idle ({:.2}% idle &gt; {})</h3>{}</div>",
+                idle::pct_idle_over(bin, config.idle_threshold),
+                format_duration(config.idle_threshold),
+                svg
+            )
+            .unwrap();
+        }
+
+        if config.has_stat(&Stats::Freq) {
+            let buckets = freq::histogram(bin, HISTOGRAM_BUCKETS);
+            let svg = histogram_svg(&buckets, |v| format!("{:.0}", v));
+            writeln!(html, "<div><h3>Access frequency</h3>{}</div>", svg).unwrap();
+        }
+
+        if config.has_stat(&Stats::Encoding) {
+            writeln!(html, "<div><h3>Encoding</h3><table>").unwrap();
+            for (type_, encoding_pcts) in encodings::type_encoding_pcts(bin) {
+                for (encoding, pct) in encoding_pcts {
+                    writeln!(
+                        html,
+                        "<tr><th>{}</th><td>{}</td><td>{:.2}%</td></tr>",
+                        escape(&type_),
+                        escape(&encoding),
+                        pct
+                    )
+                    .unwrap();
+                }
+            }
+            writeln!(html, "</table></div>").unwrap();
+        }
+
+        writeln!(html, "</div>").unwrap();
+        writeln!(html, "</section>").unwrap();
+    }
+
+    writeln!(html, "</body>").unwrap();
+    writeln!(html, "</html>").unwrap();
+
+    print!("{}", html);
+}
+
+// Draws a simple bar chart, with the range of values labeled underneath. Hovering over a bar shows
+// its range and count.
+fn histogram_svg(buckets: &[Bucket], format_value: impl Fn(f64) -> String) -> String {
+    let mut svg = String::new();
+    let label_height = 16.0;
+    let chart_height = SVG_HEIGHT - label_height;
+
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = SVG_WIDTH,
+        h = SVG_HEIGHT
+    )
+    .unwrap();
+
+    if buckets.is_empty() {
+        writeln!(
+            svg,
+            "<text class=\"axis\" x=\"0\" y=\"{}\">No data</text>",
+            chart_height / 2.0
+        )
+        .unwrap();
+    } else {
+        let max_count = buckets.iter().map(|b| b.count).max().unwrap().max(1);
+        let bar_width = SVG_WIDTH / buckets.len() as f64;
+
+        for (idx, bucket) in buckets.iter().enumerate() {
+            let bar_height = chart_height * bucket.count as f64 / max_count as f64;
+            writeln!(
+                svg,
+                "<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{} to {}: {}</title></rect>",
+                bar_width * idx as f64,
+                chart_height - bar_height,
+                (bar_width - 1.0).max(1.0),
+                bar_height,
+                escape(&format_value(bucket.lo)),
+                escape(&format_value(bucket.hi)),
+                bucket.count,
+            )
+            .unwrap();
+        }
+
+        let first = buckets.first().unwrap();
+        let last = buckets.last().unwrap();
+        writeln!(
+            svg,
+            "<text class=\"axis\" x=\"0\" y=\"{y}\">{}</text><text class=\"axis\" x=\"{x}\" y=\"{y}\" text-anchor=\"end\">{}</text>",
+            escape(&format_value(first.lo)),
+            escape(&format_value(last.hi)),
+            x = SVG_WIDTH,
+            y = SVG_HEIGHT - 4.0,
+        )
+        .unwrap();
+    }
+
+    writeln!(svg, "</svg>").unwrap();
+    svg
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_works() {
+        assert_eq!(
+            escape("<b>user:\"1\"#a&b</b>"),
+            "&lt;b&gt;user:&quot;1&quot;#a&amp;b&lt;/b&gt;"
+        );
+    }
+}
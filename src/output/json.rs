@@ -0,0 +1,206 @@
+use std::io;
+
+use clap::ValueEnum;
+use humantime::format_rfc3339_millis;
+use serde_json::{json, Map, Value};
+
+use crate::config::Config;
+use crate::data::bin::Bin;
+use crate::data::other::example_keys;
+use crate::data::{count, encodings, freq, idle, keys, memory, top, ttl, types, BinKey, Data};
+use crate::metadata::Metadata;
+use crate::stats::Stats;
+
+// Bump this whenever a field is renamed, removed, or changes meaning. Adding new fields is fine
+// without a bump, so consumers should ignore fields they don't know about.
+pub const SCHEMA_VERSION: u64 = 1;
+
+// Prints a single JSON document with the run's metadata and the stats for each bin. Unlike the
+// table output, all values are raw numbers (bytes, seconds, and percentages from 0 to 100). Stats
+// that weren't requested with --stats are left out of each bin entirely.
+pub fn json(config: &Config, metadata: &Metadata, data: &Data) {
+    let document = json!({
+        "schema_version": SCHEMA_VERSION,
+        "metadata": metadata_json(config, metadata, data),
+        "bins": data
+            .bins()
+            .map(|(bin_key, bin)| bin_json(config, metadata, data, bin_key, bin))
+            .collect::<Vec<_>>(),
+    });
+
+    serde_json::to_writer_pretty(io::stdout(), &document).unwrap();
+    println!();
+}
+
+fn metadata_json(config: &Config, metadata: &Metadata, data: &Data) -> Value {
+    let sample_mode = config.sample_mode.to_possible_value().unwrap();
+
+    json!({
+        "timestamp": format_rfc3339_millis(metadata.timestamp).to_string(),
+        "redis_version": metadata.redis_version,
+        "redis_db": metadata.redis_db,
+        "total_keys": metadata.total_keys,
+        "sample_mode": sample_mode.get_name(),
+        "bin_mode": config.bin_mode.to_possible_value().unwrap().get_name(),
+        "sample_count": data.sample_count(),
+        "match_counts": metadata.match_counts.as_ref().map(|counts| json!({
+            "examined": counts.examined,
+            "matched": counts.matched,
+        })),
+        "nodes": metadata
+            .nodes
+            .iter()
+            .map(|(addr, node)| json!({
+                "addr": addr,
+                "redis_version": node.redis_version,
+                "total_keys": node.total_keys,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn bin_json(
+    config: &Config,
+    metadata: &Metadata,
+    data: &Data,
+    bin_key: &BinKey,
+    bin: &Bin,
+) -> Value {
+    let mut bin_json = Map::new();
+    let pattern = config.patterns[bin_key.idx].pattern.as_str();
+    bin_json.insert("pattern".to_string(), pattern.into());
+    if config.has_named_captures() {
+        bin_json.insert("bin".to_string(), bin_key.name.clone().into());
+    }
+    if config.has_labels() {
+        bin_json.insert("label".to_string(), bin_key.label.clone().into());
+    }
+    bin_json.insert(
+        "keys".to_string(),
+        json!({
+            "counted": bin.key_count,
+            "est_total": keys::total_estimate(metadata, data, bin),
+            "est_total_margin": keys::total_estimate_margin(metadata, data, bin),
+        }),
+    );
+    if !config.no_example_keys {
+        bin_json.insert(
+            "example_keys".to_string(),
+            example_keys(bin, &config.key_escape).into(),
+        );
+    }
+
+    if config.has_stat(&Stats::Memory) {
+        bin_json.insert(
+            "memory".to_string(),
+            json!({
+                "sum_bytes": memory::total(bin),
+                "est_total_bytes": memory::total_estimate(metadata, data, bin),
+                "est_total_margin_bytes": memory::total_estimate_margin(metadata, data, bin),
+                "p50_bytes": memory::percentile(bin, 50_f64),
+                "p90_bytes": memory::percentile(bin, 90_f64),
+                "p99_bytes": memory::percentile(bin, 99_f64),
+            }),
+        );
+    }
+
+    if config.has_stat(&Stats::Ttl) {
+        let expiry_range = ttl::expiry_range(bin);
+        bin_json.insert(
+            "ttl".to_string(),
+            json!({
+                "pct_with_ttl": ttl::pct_with_ttl(bin),
+                "p50_seconds": ttl::percentile(bin, 50_f64),
+                "p90_seconds": ttl::percentile(bin, 90_f64),
+                "p99_seconds": ttl::percentile(bin, 99_f64),
+                "earliest_expiry": expiry_range.map(|r| format_rfc3339_millis(r.0).to_string()),
+                "latest_expiry": expiry_range.map(|r| format_rfc3339_millis(r.1).to_string()),
+            }),
+        );
+    }
+
+    if config.has_stat(&Stats::Type) {
+        let type_pcts: Map<String, Value> = types::type_pcts(bin)
+            .into_iter()
+            .map(|(type_, pct)| (type_, pct.into()))
+            .collect();
+        bin_json.insert("type_pcts".to_string(), type_pcts.into());
+    }
+
+    if config.has_stat(&Stats::Count) {
+        bin_json.insert(
+            "count".to_string(),
+            json!({
+                "sum": count::total(bin),
+                "est_total": count::total_estimate(metadata, data, bin),
+                "est_total_margin": count::total_estimate_margin(metadata, data, bin),
+                "p50": count::percentile(bin, 50_f64),
+                "p90": count::percentile(bin, 90_f64),
+                "p99": count::percentile(bin, 99_f64),
+            }),
+        );
+    }
+
+    if config.has_stat(&Stats::Encoding) {
+        let encoding_pcts: Map<String, Value> = encodings::encoding_pcts(bin)
+            .into_iter()
+            .map(|(encoding, pct)| (encoding, pct.into()))
+            .collect();
+        bin_json.insert("encoding_pcts".to_string(), encoding_pcts.into());
+
+        // For each type, the percentage of keys of that type with each encoding
+        let type_encoding_pcts: Map<String, Value> = encodings::type_encoding_pcts(bin)
+            .into_iter()
+            .map(|(type_, pcts)| {
+                let pcts: Map<String, Value> = pcts
+                    .into_iter()
+                    .map(|(encoding, pct)| (encoding, pct.into()))
+                    .collect();
+                (type_, pcts.into())
+            })
+            .collect();
+        bin_json.insert("type_encoding_pcts".to_string(), type_encoding_pcts.into());
+    }
+
+    if config.has_stat(&Stats::Idle) {
+        bin_json.insert(
+            "idle".to_string(),
+            json!({
+                "threshold_seconds": config.idle_threshold.as_secs(),
+                "pct_over_threshold": idle::pct_idle_over(bin, config.idle_threshold),
+                "p50_seconds": idle::percentile(bin, 50_f64),
+                "p90_seconds": idle::percentile(bin, 90_f64),
+                "p99_seconds": idle::percentile(bin, 99_f64),
+            }),
+        );
+    }
+
+    if config.has_stat(&Stats::Freq) {
+        bin_json.insert(
+            "freq".to_string(),
+            json!({
+                "p50": freq::percentile(bin, 50_f64),
+                "p90": freq::percentile(bin, 90_f64),
+                "p99": freq::percentile(bin, 99_f64),
+            }),
+        );
+    }
+
+    if config.top > 0 && config.has_stat(&Stats::Memory) {
+        let top_keys: Vec<Value> = top::by_memory(bin, &config.key_escape)
+            .into_iter()
+            .map(|(key, memory)| json!({"key": key, "memory_bytes": memory}))
+            .collect();
+        bin_json.insert("top_by_memory".to_string(), top_keys.into());
+    }
+
+    if config.top > 0 && config.has_stat(&Stats::Count) {
+        let top_keys: Vec<Value> = top::by_count(bin, &config.key_escape)
+            .into_iter()
+            .map(|(key, count)| json!({"key": key, "count": count}))
+            .collect();
+        bin_json.insert("top_by_count".to_string(), top_keys.into());
+    }
+
+    bin_json.into()
+}
@@ -0,0 +1,250 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+
+use crate::config::Config;
+use crate::data::BinKey;
+use crate::sampling::sample::Sample;
+use crate::stats::Stats;
+
+#[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
+pub enum RawFormat {
+    Csv,
+    Tsv,
+    Ndjson,
+}
+
+// Since samples are written as soon as they're collected, the writer is shared between everything
+// that collects samples (like each node of a Redis Cluster)
+pub type SharedRawWriter = Rc<RefCell<RawWriter>>;
+
+// Writes one record per sampled key, along with the pattern it was binned into (and the bin's name
+// and label, like the other machine-readable outputs). Only the requested --stats are included,
+// and stats that couldn't be sampled for a key are left empty (or null).
+pub struct RawWriter {
+    stats: Vec<Stats>,
+    bins: bool,
+    labels: bool,
+    out: RawOut,
+}
+
+enum RawOut {
+    Delimited(Box<csv::Writer<Box<dyn Write>>>),
+    Ndjson(Box<dyn Write>),
+}
+
+impl std::fmt::Debug for RawWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawWriter")
+            .field("stats", &self.stats)
+            .field("bins", &self.bins)
+            .field("labels", &self.labels)
+            .finish_non_exhaustive()
+    }
+}
+
+// Opens the --raw destination, if one was given. A path of "-" means STDOUT.
+pub fn open(config: &Config) -> io::Result<Option<SharedRawWriter>> {
+    let path = match &config.raw_path {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let writer: Box<dyn Write> = if path == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+
+    let stats = config.stats.clone();
+    let bins = config.has_named_captures();
+    let labels = config.has_labels();
+    let mut headers = vec!["key", "pattern"];
+    if bins {
+        headers.push("bin");
+    }
+    if labels {
+        headers.push("label");
+    }
+    for stat in stats.iter() {
+        headers.push(column_name(stat));
+    }
+    let out = match config.raw_format {
+        RawFormat::Csv => RawOut::Delimited(Box::new(delimited_writer(writer, b',', &headers)?)),
+        RawFormat::Tsv => RawOut::Delimited(Box::new(delimited_writer(writer, b'\t', &headers)?)),
+        RawFormat::Ndjson => RawOut::Ndjson(writer),
+    };
+
+    Ok(Some(Rc::new(RefCell::new(RawWriter {
+        stats,
+        bins,
+        labels,
+        out,
+    }))))
+}
+
+fn delimited_writer(
+    writer: Box<dyn Write>,
+    delimiter: u8,
+    headers: &[&str],
+) -> io::Result<csv::Writer<Box<dyn Write>>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    writer.write_record(headers)?;
+
+    Ok(writer)
+}
+
+fn column_name(stat: &Stats) -> &'static str {
+    match stat {
+        Stats::Memory => "memory_bytes",
+        Stats::Ttl => "ttl_seconds",
+        Stats::Type => "type",
+        Stats::Count => "count",
+        Stats::Encoding => "encoding",
+        Stats::Idle => "idle_seconds",
+        Stats::Freq => "freq",
+    }
+}
+
+impl RawWriter {
+    pub fn write(
+        &mut self,
+        key: &str,
+        pattern: &str,
+        bin_key: &BinKey,
+        sample: &Sample,
+    ) -> io::Result<()> {
+        match &mut self.out {
+            RawOut::Delimited(writer) => {
+                let mut record = vec![key.to_string(), pattern.to_string()];
+                if self.bins {
+                    record.push(bin_key.name.clone());
+                }
+                if self.labels {
+                    record.push(bin_key.label.clone().unwrap_or_default());
+                }
+                for stat in self.stats.iter() {
+                    let value = match stat {
+                        Stats::Memory => sample.memory.get().map(|v| v.to_string()),
+                        Stats::Ttl => sample.ttl_ms.get().map(|&v| ttl_seconds(v).to_string()),
+                        Stats::Type => sample.type_.get().cloned(),
+                        Stats::Count => sample.count.get().map(|v| v.to_string()),
+                        Stats::Encoding => sample.encoding.get().cloned(),
+                        Stats::Idle => sample.idle.get().map(|v| v.to_string()),
+                        Stats::Freq => sample.freq.get().map(|v| v.to_string()),
+                    };
+                    record.push(value.unwrap_or_default());
+                }
+                writer.write_record(&record)?;
+            }
+            RawOut::Ndjson(writer) => {
+                let mut record = serde_json::Map::new();
+                record.insert("key".to_string(), key.into());
+                record.insert("pattern".to_string(), pattern.into());
+                if self.bins {
+                    record.insert("bin".to_string(), bin_key.name.clone().into());
+                }
+                if self.labels {
+                    record.insert("label".to_string(), bin_key.label.clone().into());
+                }
+                for stat in self.stats.iter() {
+                    let value = match stat {
+                        Stats::Memory => sample.memory.get().copied().into(),
+                        Stats::Ttl => sample.ttl_ms.get().map(|&v| ttl_seconds(v)).into(),
+                        Stats::Type => sample.type_.get().cloned().into(),
+                        Stats::Count => sample.count.get().copied().into(),
+                        Stats::Encoding => sample.encoding.get().cloned().into(),
+                        Stats::Idle => sample.idle.get().copied().into(),
+                        Stats::Freq => sample.freq.get().copied().into(),
+                    };
+                    record.insert(column_name(stat).to_string(), value);
+                }
+                serde_json::to_writer(&mut *writer, &record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.out {
+            RawOut::Delimited(writer) => writer.flush(),
+            RawOut::Ndjson(writer) => writer.flush(),
+        }
+    }
+}
+
+// TTLs are sampled in milliseconds, but written in seconds (like everywhere else). A TTL of -1 (no
+// TTL) is written as-is.
+fn ttl_seconds(ttl_ms: i64) -> serde_json::Value {
+    if ttl_ms < 0 {
+        ttl_ms.into()
+    } else {
+        (ttl_ms as f64 / 1000.0).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::config::Config;
+    use crate::data::Data;
+    use crate::sampling::sample::Sample;
+    use crate::sampling::sample::SampleValue::*;
+
+    #[test]
+    fn raw_records_have_the_pattern_bin_and_label() {
+        let dir = std::env::temp_dir().join(format!(
+            "redis-keyspace-stats-raw-test-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("raw.csv");
+
+        let mut config = Config::parse_from([
+            "test",
+            "--stats",
+            "memory",
+            "--raw",
+            path.to_str().unwrap(),
+            "--label",
+            "Users=re:^user:(?P<id>\\d+):",
+            "re:^user:(?P<id>\\d+):",
+        ]);
+        config.normalize();
+        let raw = super::open(&config).unwrap().unwrap();
+        let mut data = Data::new(&config);
+        data.stream_raw_to(raw.clone());
+        for key in ["user:42:sessions", "lock"] {
+            let sample = Sample {
+                exists: Unsampled,
+                memory: Sampled(10),
+                ttl_ms: Unsampled,
+                expires_at: Unsampled,
+                type_: Unsampled,
+                count: Unsampled,
+                encoding: Unsampled,
+                idle: Unsampled,
+                freq: Unsampled,
+            };
+            data.add_sample(key.as_bytes().to_vec(), sample);
+        }
+        raw.borrow_mut().flush().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            written.lines().collect::<Vec<_>>(),
+            vec![
+                "key,pattern,bin,label,memory_bytes",
+                "user:42:sessions,re:^user:(?P<id>\\d+):,id=42,Users (id=42),10",
+                "lock,*,*,,10",
+            ]
+        );
+    }
+}
@@ -1,117 +1,247 @@
 use std::time::Duration;
 
-use humantime::format_duration;
+use humantime::{format_duration, format_rfc3339_seconds};
 use pretty_bytes::converter::convert;
 use prettytable::{Cell, Row, Table};
 
 use crate::config::Config;
+use crate::data::bin::Bin;
 use crate::data::other::example_keys;
-use crate::data::{keys, memory, ttl, types, Data};
+use crate::data::{count, encodings, freq, idle, keys, memory, top, ttl, types, Data};
 use crate::metadata::Metadata;
 use crate::stats::Stats;
 
 pub fn table(config: &Config, metadata: &Metadata, data: &Data) {
-    /***************/
-    /* ADD HEADERS */
-    /***************/
-
     let mut table = Table::new();
     table.set_format(*prettytable::format::consts::FORMAT_BOX_CHARS);
 
-    table.add_row(Row::new(vec![
-        Cell::new("Pattern"),
-        Cell::new("Keys"),
-        Cell::new("Example keys"),
-    ]));
+    table.add_row(Row::new(
+        headers(config).iter().map(|h| Cell::new(h)).collect(),
+    ));
+
+    for (bin_key, bin) in data.bins() {
+        table.add_row(Row::new(
+            cells(config, metadata, data, bin_key.display_name(), bin)
+                .iter()
+                .map(|c| Cell::new(c))
+                .collect(),
+        ));
+    }
+
+    // Print the table to stdout
+    table.printstd();
+}
+
+// The headers and cells of the table are also used by the HTML output, which shows the same
+// summary. Cells may contain multiple lines, separated by "\n".
+pub fn headers(config: &Config) -> Vec<&'static str> {
+    let mut headers = vec!["Pattern", "Keys"];
+
+    if !config.no_example_keys {
+        headers.push("Example keys");
+    }
 
     if config.has_stat(&Stats::Memory) {
-        let row = table.get_mut_row(0).unwrap();
-        row.add_cell(Cell::new("Memory"));
+        headers.push("Memory");
     }
 
     if config.has_stat(&Stats::Ttl) {
-        let row = table.get_mut_row(0).unwrap();
-        row.add_cell(Cell::new("TTL"));
+        headers.push("TTL");
     }
 
     if config.has_stat(&Stats::Type) {
-        let row = table.get_mut_row(0).unwrap();
-        row.add_cell(Cell::new("Type"));
+        headers.push("Type");
+    }
+
+    if config.has_stat(&Stats::Count) {
+        headers.push("Count");
+    }
+
+    if config.has_stat(&Stats::Encoding) {
+        headers.push("Encoding");
+    }
+
+    if config.has_stat(&Stats::Idle) {
+        headers.push("Idle");
+    }
+
+    if config.has_stat(&Stats::Freq) {
+        headers.push("Freq");
     }
 
-    /************/
-    /* ADD ROWS */
-    /************/
+    if config.top > 0 && config.has_stat(&Stats::Memory) {
+        headers.push("Top by memory");
+    }
 
-    for pattern in data.patterns() {
-        let bin = data.bins().get(pattern).unwrap();
+    if config.top > 0 && config.has_stat(&Stats::Count) {
+        headers.push("Top by count");
+    }
+
+    headers
+}
 
-        let mut row = Row::new(vec![
-            Cell::new(pattern.as_str()),
-            Cell::new(
-                &vec![
-                    format!("{} counted", bin.len(),),
-                    format!("{} est. total", keys::total_estimate(metadata, data, bin)),
-                ]
-                .join("\n"),
+pub fn cells(
+    config: &Config,
+    metadata: &Metadata,
+    data: &Data,
+    name: &str,
+    bin: &Bin,
+) -> Vec<String> {
+    let mut cells = vec![
+        name.to_string(),
+        [
+            format!("{} counted", bin.key_count,),
+            format!(
+                "{} est. total",
+                with_margin(
+                    keys::total_estimate(metadata, data, bin).to_string(),
+                    keys::total_estimate_margin(metadata, data, bin).map(|m| m.to_string()),
+                ),
             ),
-            Cell::new(&example_keys(bin).join("\n")),
-        ]);
-
-        if config.has_stat(&Stats::Memory) {
-            row.add_cell(Cell::new(
-                &vec![
-                    format!("{} (sum)", convert(memory::total(data, bin) as f64)),
-                    format!(
-                        "{} (est. total)",
+        ]
+        .join("\n"),
+    ];
+
+    if !config.no_example_keys {
+        cells.push(example_keys(bin, &config.key_escape).join("\n"));
+    }
+
+    if config.has_stat(&Stats::Memory) {
+        cells.push(
+            [
+                format!("{} (sum)", convert(memory::total(bin) as f64)),
+                format!(
+                    "{} (est. total)",
+                    with_margin(
                         convert(memory::total_estimate(metadata, data, bin) as f64),
+                        memory::total_estimate_margin(metadata, data, bin)
+                            .map(|m| convert(m as f64)),
                     ),
-                    format!("{} (p50)", convert(memory::percentile(data, bin, 50_f64))),
-                    format!("{} (p90)", convert(memory::percentile(data, bin, 90_f64))),
-                    format!("{} (p99)", convert(memory::percentile(data, bin, 99_f64))),
-                ]
-                .join("\n"),
+                ),
+                format!("{} (p50)", convert(memory::percentile(bin, 50_f64))),
+                format!("{} (p90)", convert(memory::percentile(bin, 90_f64))),
+                format!("{} (p99)", convert(memory::percentile(bin, 99_f64))),
+            ]
+            .join("\n"),
+        );
+    }
+
+    if config.has_stat(&Stats::Ttl) {
+        let mut ttl_lines = vec![
+            format!("{:.2}% have TTL", ttl::pct_with_ttl(bin)),
+            format!(
+                "{} (p50)",
+                format_duration(Duration::from_secs_f64(ttl::percentile(bin, 50_f64)))
+            ),
+            format!(
+                "{} (p90)",
+                format_duration(Duration::from_secs_f64(ttl::percentile(bin, 90_f64)))
+            ),
+            format!(
+                "{} (p99)",
+                format_duration(Duration::from_secs_f64(ttl::percentile(bin, 99_f64)))
+            ),
+        ];
+        if let Some((earliest, latest)) = ttl::expiry_range(bin) {
+            ttl_lines.push(format!(
+                "earliest expiry at {}",
+                format_rfc3339_seconds(earliest)
+            ));
+            ttl_lines.push(format!(
+                "latest expiry at {}",
+                format_rfc3339_seconds(latest)
             ));
         }
+        cells.push(ttl_lines.join("\n"));
+    }
 
-        if config.has_stat(&Stats::Ttl) {
-            row.add_cell(Cell::new(
-                &vec![
-                    format!("{:.2}% have TTL", ttl::pct_with_ttl(data, bin)),
-                    format!(
-                        "{} (p50)",
-                        format_duration(Duration::from_secs_f64(ttl::percentile(
-                            data, bin, 50_f64
-                        )))
-                    ),
-                    format!(
-                        "{} (p90)",
-                        format_duration(Duration::from_secs_f64(ttl::percentile(
-                            data, bin, 90_f64
-                        )))
-                    ),
-                    format!(
-                        "{} (p99)",
-                        format_duration(Duration::from_secs_f64(ttl::percentile(
-                            data, bin, 99_f64
-                        )))
-                    ),
-                ]
-                .join("\n"),
-            ));
+    if config.has_stat(&Stats::Type) {
+        let mut type_lines = Vec::new();
+        for (type_, pct) in types::type_pcts(bin) {
+            type_lines.push(format!("{:.2}% {}", pct, type_));
         }
+        cells.push(type_lines.join("\n"));
+    }
+
+    if config.has_stat(&Stats::Count) {
+        cells.push(
+            [
+                format!("{} (sum)", count::total(bin)),
+                format!(
+                    "{} (est. total)",
+                    with_margin(
+                        count::total_estimate(metadata, data, bin).to_string(),
+                        count::total_estimate_margin(metadata, data, bin).map(|m| m.to_string()),
+                    ),
+                ),
+                format!("{:.0} (p50)", count::percentile(bin, 50_f64)),
+                format!("{:.0} (p90)", count::percentile(bin, 90_f64)),
+                format!("{:.0} (p99)", count::percentile(bin, 99_f64)),
+            ]
+            .join("\n"),
+        );
+    }
 
-        if config.has_stat(&Stats::Type) {
-            let mut type_lines = Vec::new();
-            for (type_, pct) in types::type_pcts(data, bin) {
-                type_lines.push(format!("{:.2}% {}", pct, type_));
+    if config.has_stat(&Stats::Encoding) {
+        let mut encoding_lines = Vec::new();
+        for (type_, encoding_pcts) in encodings::type_encoding_pcts(bin) {
+            for (encoding, pct) in encoding_pcts {
+                encoding_lines.push(format!("{:.2}% of {}: {}", pct, type_, encoding));
             }
-            row.add_cell(Cell::new(&type_lines.join("\n")));
         }
+        cells.push(encoding_lines.join("\n"));
+    }
 
-        table.add_row(row);
+    if config.has_stat(&Stats::Idle) {
+        let format_idle =
+            |pct| format_duration(Duration::from_secs_f64(idle::percentile(bin, pct)));
+        cells.push(
+            [
+                format!(
+                    "{:.2}% idle > {}",
+                    idle::pct_idle_over(bin, config.idle_threshold),
+                    format_duration(config.idle_threshold)
+                ),
+                format!("{} (p50)", format_idle(50_f64)),
+                format!("{} (p90)", format_idle(90_f64)),
+                format!("{} (p99)", format_idle(99_f64)),
+            ]
+            .join("\n"),
+        );
     }
 
-    // Print the table to stdout
-    table.printstd();
+    if config.has_stat(&Stats::Freq) {
+        cells.push(
+            [
+                format!("{:.0} (p50)", freq::percentile(bin, 50_f64)),
+                format!("{:.0} (p90)", freq::percentile(bin, 90_f64)),
+                format!("{:.0} (p99)", freq::percentile(bin, 99_f64)),
+            ]
+            .join("\n"),
+        );
+    }
+
+    if config.top > 0 && config.has_stat(&Stats::Memory) {
+        let mut top_lines = Vec::new();
+        for (key, memory) in top::by_memory(bin, &config.key_escape) {
+            top_lines.push(format!("{} ({})", key, convert(memory as f64)));
+        }
+        cells.push(top_lines.join("\n"));
+    }
+
+    if config.top > 0 && config.has_stat(&Stats::Count) {
+        let mut top_lines = Vec::new();
+        for (key, count) in top::by_count(bin, &config.key_escape) {
+            top_lines.push(format!("{} ({})", key, count));
+        }
+        cells.push(top_lines.join("\n"));
+    }
+
+    cells
+}
+
+// Adds the margin of error (at 95% confidence) to an estimated total. If there were too few samples
+// to compute one, it's shown as "?".
+pub(super) fn with_margin(estimate: String, margin: Option<String>) -> String {
+    format!("{} ± {}", estimate, margin.as_deref().unwrap_or("?"))
 }
@@ -0,0 +1,202 @@
+use pretty_bytes::converter::convert;
+use prettytable::{Cell, Row, Table};
+
+use crate::config::Config;
+use crate::data::math::{pct_keyspace_sampled, total_estimate_margin};
+use crate::data::tree::PrefixNode;
+use crate::data::Data;
+use crate::metadata::Metadata;
+use crate::output::table::with_margin;
+use crate::stats::Stats;
+
+// Prints each prefix on its own row, indented under its parent, with the largest prefixes first.
+// Like the table output, estimated totals come with their margin of error at 95% confidence.
+// Prefixes with less than --tree-min-pct of both the keys and memory sampled are hidden (along with
+// everything under them), so the tree stays readable.
+pub fn tree(config: &Config, metadata: &Metadata, data: &Data) {
+    let tree = data.tree().expect("No prefix tree was collected");
+
+    let mut table = Table::new();
+    table.set_format(*prettytable::format::consts::FORMAT_BOX_CHARS);
+
+    let mut headers = vec!["Prefix", "Keys"];
+    if config.has_stat(&Stats::Memory) {
+        headers.push("Memory");
+    }
+    if config.has_stat(&Stats::Ttl) {
+        headers.push("TTL");
+    }
+    table.add_row(Row::new(headers.iter().map(|h| Cell::new(h)).collect()));
+
+    let mut rows = vec![];
+    add_rows(
+        config,
+        &tree.root,
+        &tree.root,
+        "",
+        0,
+        tree.delimiter(),
+        &mut rows,
+    );
+
+    let pct_of_keyspace_sampled = pct_keyspace_sampled(metadata, data);
+    for (prefix, node) in rows {
+        let key_count = node.key_count as f64;
+        let mut cells = vec![
+            prefix,
+            format!(
+                "{} est. ({:.1}%)",
+                with_margin(
+                    (key_count / pct_of_keyspace_sampled).round().to_string(),
+                    total_estimate_margin(metadata, data, key_count, key_count)
+                        .map(|m| m.round().to_string()),
+                ),
+                pct_of(node.key_count, tree.root.key_count)
+            ),
+        ];
+        if config.has_stat(&Stats::Memory) {
+            cells.push(format!(
+                "{} est. ({:.1}%)",
+                with_margin(
+                    convert((node.memory as f64 / pct_of_keyspace_sampled).round()),
+                    total_estimate_margin(
+                        metadata,
+                        data,
+                        node.memory as f64,
+                        node.memory_sum_of_squares
+                    )
+                    .map(|m| convert(m.round())),
+                ),
+                pct_of(node.memory, tree.root.memory)
+            ));
+        }
+        if config.has_stat(&Stats::Ttl) {
+            cells.push(format!(
+                "{:.1}% have TTL",
+                pct_of(node.with_ttl_count, node.ttl_count)
+            ));
+        }
+        table.add_row(Row::new(cells.iter().map(|c| Cell::new(c)).collect()));
+    }
+
+    table.printstd();
+}
+
+// Walks the tree depth-first, adding each visible prefix (indented by its depth) and its node
+fn add_rows<'a>(
+    config: &Config,
+    root: &PrefixNode,
+    node: &'a PrefixNode,
+    prefix: &str,
+    depth: usize,
+    delimiter: char,
+    rows: &mut Vec<(String, &'a PrefixNode)>,
+) {
+    for (segment, child) in visible_children(config, root, node) {
+        let child_prefix = if depth == 0 {
+            segment.clone()
+        } else {
+            format!("{}{}{}", prefix, delimiter, segment)
+        };
+        rows.push((format!("{}{}", "  ".repeat(depth), child_prefix), child));
+        add_rows(
+            config,
+            root,
+            child,
+            &child_prefix,
+            depth + 1,
+            delimiter,
+            rows,
+        );
+    }
+}
+
+// The children with at least --tree-min-pct of the keys or memory, largest first (by memory, if
+// that stat was collected)
+fn visible_children<'a>(
+    config: &Config,
+    root: &PrefixNode,
+    node: &'a PrefixNode,
+) -> Vec<(&'a String, &'a PrefixNode)> {
+    let min_pct = config.tree_min_pct as f64;
+    let by_memory = config.has_stat(&Stats::Memory);
+
+    let mut children: Vec<(&String, &PrefixNode)> = node
+        .children
+        .iter()
+        .filter(|(_segment, child)| {
+            pct_of(child.key_count, root.key_count) >= min_pct
+                || (by_memory && pct_of(child.memory, root.memory) >= min_pct)
+        })
+        .collect();
+    children.sort_by(|(a_segment, a), (b_segment, b)| {
+        let size = |node: &PrefixNode| {
+            if by_memory {
+                node.memory
+            } else {
+                node.key_count
+            }
+        };
+        size(b)
+            .cmp(&size(a))
+            .then(b.key_count.cmp(&a.key_count))
+            .then(a_segment.cmp(b_segment))
+    });
+    children
+}
+
+fn pct_of(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_children_works() {
+        let (mut config, _conn) = crate::tests::test_config_and_conn();
+        config.stats = vec![Stats::Memory];
+        config.tree_min_pct = 10;
+
+        let mut root = PrefixNode {
+            key_count: 100,
+            memory: 1000,
+            ..Default::default()
+        };
+        for (segment, key_count, memory) in [
+            ("few-but-large", 5, 500),
+            ("many-but-small", 90, 400),
+            ("tiny", 5, 100),
+            ("hidden", 0, 0),
+        ] {
+            root.children.insert(
+                segment.to_string(),
+                PrefixNode {
+                    key_count,
+                    memory,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let segments = |config: &Config| -> Vec<String> {
+            visible_children(config, &root, &root)
+                .into_iter()
+                .map(|(segment, _child)| segment.clone())
+                .collect()
+        };
+        assert_eq!(
+            segments(&config),
+            vec!["few-but-large", "many-but-small", "tiny"]
+        );
+
+        // Without memory, only key counts matter
+        config.stats = vec![Stats::Ttl];
+        assert_eq!(segments(&config), vec!["many-but-small"]);
+    }
+}
@@ -2,10 +2,16 @@ use redis::Connection;
 
 use crate::config::Config;
 use crate::data::Data;
+use crate::metadata::Metadata;
+use crate::output::raw::SharedRawWriter;
+use crate::stats::Stats;
 
+pub mod discover;
 pub mod sample;
 mod sample_all;
 mod sample_random;
+mod script;
+mod throttle;
 
 #[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
 pub enum SampleMode {
@@ -13,10 +19,51 @@ pub enum SampleMode {
     Random,
 }
 
-pub fn collect_samples(config: &Config, conn: &mut Connection) -> Data {
+// How stats are collected for random samples. The Lua backend gets random keys AND their stats in a
+// single server-side script call, which saves a round trip per batch.
+#[derive(clap::ValueEnum, Eq, PartialEq, Clone, Debug)]
+pub enum SampleBackend {
+    Pipeline,
+    Lua,
+}
+
+pub fn collect_samples(
+    config: &Config,
+    conn: &mut Connection,
+    metadata: &mut Metadata,
+    raw: Option<SharedRawWriter>,
+) -> Data {
     use SampleMode::*;
+
+    if config.has_stat(&Stats::Count) {
+        if let Err(e) = no_touch(conn) {
+            eprintln!(
+                "Counting elements will update keys' idle time and access frequency ({})",
+                e
+            );
+        }
+    }
+
+    let mut data = Data::new(config);
+    if let Some(raw) = raw {
+        data.stream_raw_to(raw);
+    }
+
     match config.sample_mode {
-        All => sample_all::sample_all(config, conn),
-        Random => sample_random::sample_random(config, conn),
+        All => sample_all::sample_all(config, conn, metadata, &mut data),
+        Random => sample_random::sample_random(config, conn, metadata, &mut data),
     }
+
+    data
+}
+
+// Counting elements uses commands like HLEN, which (unlike everything else we run) count as an
+// access to the key. CLIENT NO-TOUCH (Redis 7.2+) stops them from changing the key's idle time and
+// access frequency for the rest of the connection.
+pub fn no_touch(conn: &mut Connection) -> Result<(), String> {
+    redis::cmd("CLIENT")
+        .arg("NO-TOUCH")
+        .arg("ON")
+        .query::<()>(conn)
+        .map_err(|e| e.to_string())
 }
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+
+use redis::Connection;
+
+use crate::config::Config;
+use crate::data::ids::IdMatcher;
+use crate::data::pattern::Glob;
+use crate::data::Key;
+use crate::sampling::throttle::Throttle;
+
+// How many random keys to look at when discovering patterns (at most --max-draws)
+const DISCOVERY_DRAWS: usize = 1000;
+
+// Templates that fewer than this fraction of the keys have are merged into more general ones
+const MIN_TEMPLATE_SHARE: f64 = 0.02;
+
+// Only the most common templates are kept, so the output stays readable
+const MAX_PATTERNS: usize = 20;
+
+// Keys are split into segments on these characters, which are kept as-is in templates
+const DELIMITERS: [char; 4] = [':', '#', '/', '.'];
+
+// Finds patterns for --auto-patterns by looking at a batch of random keys (that match the --match
+// patterns, if given). This runs before sampling, with the same throttling, so the discovered
+// patterns can be used for binning just like ones given on the command line.
+pub fn discover_patterns(config: &Config, conn: &mut Connection) -> Result<Vec<Glob>, String> {
+    let mut throttle = Throttle::new(config);
+    let mut keys = HashSet::new();
+    let mut draws_left = DISCOVERY_DRAWS.min(config.max_draws);
+
+    while draws_left > 0 {
+        let batch_size = throttle.batch_size().min(draws_left);
+        draws_left -= batch_size;
+
+        let mut pipe = redis::pipe();
+        for _ in 0..batch_size {
+            pipe.cmd("RANDOMKEY");
+        }
+        let batch: Vec<Option<Key>> = throttle
+            .time(|| pipe.query(conn))
+            .map_err(|e| e.to_string())?;
+
+        // RANDOMKEY returns nil if the database is empty
+        if batch.iter().all(Option::is_none) {
+            break;
+        }
+        keys.extend(batch.into_iter().flatten().filter(|k| config.is_match(k)));
+
+        throttle.end_batch(conn);
+    }
+
+    // Keys that aren't valid UTF-8 are inferred from their text with any invalid bytes replaced
+    // (see escape for how those still match)
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|key| String::from_utf8_lossy(key).into_owned())
+        .collect();
+    Ok(infer_patterns(&keys))
+}
+
+// Turns keys into templates like "user:*#friends", by splitting them on DELIMITERS and replacing
+// segments that look like IDs (numbers, UUIDs, hex hashes, and dates) with "*". Templates that are
+// too rare to be worth their own bin are generalized one segment at a time ("user:*#friends", then
+// "user:*#*", then "user:*") until they're common enough, or dropped entirely if they'd end up as
+// just "*". More specific patterns are returned first, since the first matching pattern wins.
+pub fn infer_patterns(keys: &[String]) -> Vec<Glob> {
+    let ids = IdMatcher::new();
+
+    let mut counts: HashMap<Template, usize> = HashMap::new();
+    for key in keys {
+        *counts.entry(Template::new(key, &ids)).or_insert(0) += 1;
+    }
+
+    let min_count = ((keys.len() as f64 * MIN_TEMPLATE_SHARE).ceil() as usize).max(2);
+    loop {
+        let (common, rare): (Vec<_>, Vec<_>) = counts
+            .into_iter()
+            .partition(|(_template, count)| *count >= min_count);
+        counts = common.into_iter().collect();
+        if rare.is_empty() {
+            break;
+        }
+        for (template, count) in rare {
+            if let Some(general) = template.generalize() {
+                *counts.entry(general).or_insert(0) += count;
+            }
+        }
+    }
+
+    // Keep the most common templates, then put the most specific ones first
+    let mut templates: Vec<(Template, usize)> = counts
+        .into_iter()
+        .filter(|(template, _count)| !template.is_catch_all())
+        .collect();
+    templates.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    templates.truncate(MAX_PATTERNS);
+    templates.sort_by(|(a, a_count), (b, b_count)| {
+        (b.literal_len().cmp(&a.literal_len()))
+            .then(b_count.cmp(a_count))
+            .then(a.cmp(b))
+    });
+
+    templates
+        .iter()
+        .map(|(template, _count)| template.to_pattern())
+        .collect()
+}
+
+// A key split into segments (None for a "*") and the delimiters between them
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Debug)]
+struct Template {
+    segments: Vec<Option<String>>,
+    delimiters: Vec<char>,
+}
+
+impl Template {
+    fn new(key: &str, ids: &IdMatcher) -> Template {
+        let mut segments = vec![String::new()];
+        let mut delimiters = vec![];
+        for c in key.chars() {
+            if DELIMITERS.contains(&c) {
+                delimiters.push(c);
+                segments.push(String::new());
+            } else {
+                segments.last_mut().unwrap().push(c);
+            }
+        }
+
+        Template {
+            segments: segments
+                .into_iter()
+                .map(|segment| (!ids.is_id(&segment)).then_some(segment))
+                .collect(),
+            delimiters,
+        }
+    }
+
+    // Replaces the last segment with "*", or if it already is one, drops it along with its
+    // delimiter and replaces the segment before it instead
+    fn generalize(&self) -> Option<Template> {
+        let mut general = self.clone();
+        let last = general.segments.len() - 1;
+        if general.segments[last].is_some() {
+            general.segments[last] = None;
+        } else if last > 0 {
+            general.segments.pop();
+            general.delimiters.pop();
+            general.segments[last - 1] = None;
+        } else {
+            return None;
+        }
+        Some(general)
+    }
+
+    fn is_catch_all(&self) -> bool {
+        self.segments == [None]
+    }
+
+    // The number of literal (non-"*") characters
+    fn literal_len(&self) -> usize {
+        self.segments
+            .iter()
+            .flatten()
+            .map(|s| s.len())
+            .sum::<usize>()
+            + self.delimiters.len()
+    }
+
+    fn to_pattern(&self) -> Glob {
+        let mut pattern = String::new();
+        for (idx, segment) in self.segments.iter().enumerate() {
+            if idx > 0 {
+                pattern.push(self.delimiters[idx - 1]);
+            }
+            match segment {
+                Some(literal) => pattern.push_str(&escape(literal)),
+                None => pattern.push('*'),
+            }
+        }
+        Glob::new(&pattern)
+    }
+}
+
+// Escapes glob syntax in part of a key, so SCAN's MATCH (see glob_matches) reads it as literal
+// characters. Globs match raw bytes, so the U+FFFD that stands in for bytes that aren't valid UTF-8
+// becomes a "*" that matches them.
+fn escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        match c {
+            char::REPLACEMENT_CHARACTER => escaped.push('*'),
+            '*' | '?' | '[' => escaped.extend(['[', c, ']']),
+            ']' | '\\' => escaped.extend(['\\', c]),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::pattern::glob_matches;
+
+    #[test]
+    fn infer_patterns_works() {
+        let mut keys = vec![];
+        for id in 1..=50 {
+            keys.push(format!("user:{}#friends", id));
+            keys.push(format!("user:{}#messages", id));
+            keys.push(format!("session:{:08x}-1234-4abc-8def-0123456789ab", id));
+            keys.push(format!("cache/2023-09-{:02}/page.html", id % 28 + 1));
+        }
+        // Too rare for their own templates, but together they're common enough
+        keys.push("user:1#likes".to_string());
+        keys.push("user:2#follows".to_string());
+        keys.push("user:3#blocks".to_string());
+        keys.push("user:4#mutes".to_string());
+        keys.push("user:5#pins".to_string());
+        // Too rare to be a pattern at all
+        keys.push("lock".to_string());
+
+        let patterns = infer_patterns(&keys);
+        let patterns: Vec<&str> = patterns.iter().map(|p| p.as_str()).collect();
+        assert_eq!(
+            patterns,
+            vec![
+                "cache/*/page.html",
+                "user:*#messages",
+                "user:*#friends",
+                "session:*",
+                "user:*#*",
+            ]
+        );
+
+        // Glob syntax in keys is escaped
+        let keys = vec![r"a[*\]:1".to_string(), r"a[*\]:2".to_string()];
+        let pattern = &infer_patterns(&keys)[0];
+        assert_eq!(pattern.as_str(), r"a[[][*]\\\]:*");
+        assert!(glob_matches(pattern, br"a[*\]:3"));
+        assert!(!glob_matches(pattern, b"a[x]:3"));
+        let keys = vec!["bin\u{fffd}:1".to_string(), "bin\u{fffd}:2".to_string()];
+        let pattern = &infer_patterns(&keys)[0];
+        assert_eq!(pattern.as_str(), "bin*:*");
+        assert!(glob_matches(pattern, b"bin\xff\x00:3"));
+        assert!(infer_patterns(&[]).is_empty());
+    }
+}
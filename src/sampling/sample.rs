@@ -1,16 +1,24 @@
 use std::fmt::Debug;
+use std::time::{Duration, SystemTime};
 
 use redis::{Connection, Value};
 
 use crate::config::Config;
+use crate::data::Key;
 use crate::stats::Stats;
 
 #[derive(Eq, PartialEq, Debug)]
 pub struct Sample {
     pub exists: SampleValue<bool>,
     pub memory: SampleValue<u64>,
-    pub ttl: SampleValue<i64>,
+    // Like PTTL, this is -1 if the key has no TTL
+    pub ttl_ms: SampleValue<i64>,
+    pub expires_at: SampleValue<SystemTime>,
     pub type_: SampleValue<String>,
+    pub count: SampleValue<u64>,
+    pub encoding: SampleValue<String>,
+    pub idle: SampleValue<u64>,
+    pub freq: SampleValue<u64>,
 }
 
 #[allow(dead_code)]
@@ -21,12 +29,17 @@ impl Sample {
         let mut sample = Sample {
             exists: Unsampled,
             memory: Unsampled,
-            ttl: Unsampled,
+            ttl_ms: Unsampled,
+            expires_at: Unsampled,
             type_: Unsampled,
+            count: Unsampled,
+            encoding: Unsampled,
+            idle: Unsampled,
+            freq: Unsampled,
         };
 
         // Whether this key exists is always at the 0th index
-        let exists = data.get(0);
+        let exists = data.first();
         sample.exists = match exists {
             Some(&Value::Int(0)) => Sampled(false),
             Some(&Value::Int(1)) => Sampled(true),
@@ -35,7 +48,7 @@ impl Sample {
 
         // The rest of the stats are optional. To account for this, we use an offset for indexing
         // into the "data" vector. For each stat we read, it is incremented. Note that the order of
-        // stats checked here MUST match the order they were collected in the sample_key function.
+        // stats checked here MUST match the order they were collected in the sample_keys function.
         let mut data_idx = 1;
 
         if config.has_stat(&Stats::Memory) {
@@ -48,16 +61,23 @@ impl Sample {
         }
 
         if config.has_stat(&Stats::Ttl) {
-            let ttl = data.get(data_idx);
+            let ttl_ms = data.get(data_idx);
             data_idx += 1;
-            sample.ttl = match ttl {
-                Some(Value::Int(ttl)) => Sampled(*ttl),
+            sample.ttl_ms = match ttl_ms {
+                Some(Value::Int(ttl_ms)) => Sampled(*ttl_ms),
                 _ => NotFound,
-            }
+            };
+
+            // The TTL counts down from when Redis replied, which was (almost) just now
+            sample.expires_at = match sample.ttl_ms {
+                Sampled(ttl_ms) if ttl_ms >= 0 => {
+                    Sampled(SystemTime::now() + Duration::from_millis(ttl_ms as u64))
+                }
+                _ => NotFound,
+            };
         }
 
-        #[allow(unused_assignments)]
-        if config.has_stat(&Stats::Type) {
+        if collects_type(config) {
             let type_ = data.get(data_idx);
             data_idx += 1;
             sample.type_ = match type_ {
@@ -66,6 +86,34 @@ impl Sample {
             }
         }
 
+        if config.has_stat(&Stats::Encoding) {
+            let encoding = data.get(data_idx);
+            data_idx += 1;
+            sample.encoding = match encoding {
+                Some(Value::Data(e)) => Sampled(String::from_utf8_lossy(e).to_string()),
+                _ => NotFound,
+            }
+        }
+
+        if config.has_stat(&Stats::Idle) {
+            let idle = data.get(data_idx);
+            data_idx += 1;
+            sample.idle = match idle {
+                Some(Value::Int(idle)) => Sampled(*idle as u64),
+                _ => NotFound,
+            }
+        }
+
+        #[allow(unused_assignments)]
+        if config.has_stat(&Stats::Freq) {
+            let freq = data.get(data_idx);
+            data_idx += 1;
+            sample.freq = match freq {
+                Some(Value::Int(freq)) => Sampled(*freq as u64),
+                _ => NotFound,
+            }
+        }
+
         sample
     }
 
@@ -77,13 +125,29 @@ impl Sample {
         *self.memory.value()
     }
 
-    pub fn ttl(&self) -> i64 {
-        *self.ttl.value()
+    pub fn ttl_ms(&self) -> i64 {
+        *self.ttl_ms.value()
     }
 
     pub fn type_(&self) -> String {
         self.type_.value().clone()
     }
+
+    pub fn count(&self) -> u64 {
+        *self.count.value()
+    }
+
+    pub fn encoding(&self) -> String {
+        self.encoding.value().clone()
+    }
+
+    pub fn idle(&self) -> u64 {
+        *self.idle.value()
+    }
+
+    pub fn freq(&self) -> u64 {
+        *self.freq.value()
+    }
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -103,58 +167,231 @@ impl<T> SampleValue<T> {
             Sampled(val) => val,
         }
     }
+
+    // Like value(), but returns None instead of panicking if this wasn't sampled
+    pub fn get(&self) -> Option<&T> {
+        match self {
+            SampleValue::Sampled(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+// The element count of a key depends on its type, and encodings are broken down by type, so TYPE is
+// needed for those even if the type stat itself wasn't requested
+pub fn collects_type(config: &Config) -> bool {
+    config.has_stat(&Stats::Type)
+        || config.has_stat(&Stats::Count)
+        || config.has_stat(&Stats::Encoding)
+}
+
+// The number of commands (and therefore replies) in the first pipeline for each key
+pub fn commands_per_key(config: &Config) -> usize {
+    let stats = [
+        Stats::Memory,
+        Stats::Ttl,
+        Stats::Encoding,
+        Stats::Idle,
+        Stats::Freq,
+    ];
+    1 + stats.iter().filter(|stat| config.has_stat(stat)).count() + collects_type(config) as usize
+}
+
+// The command that returns the number of elements in a key of the given type. For strings, this is
+// the length in bytes. Types added by modules have no such command.
+pub fn count_command(type_: &str) -> Option<&'static str> {
+    match type_ {
+        "string" => Some("STRLEN"),
+        "list" => Some("LLEN"),
+        "set" => Some("SCARD"),
+        "zset" => Some("ZCARD"),
+        "hash" => Some("HLEN"),
+        "stream" => Some("XLEN"),
+        _ => None,
+    }
+}
+
+pub fn decode_count(value: Option<&Value>) -> SampleValue<u64> {
+    match value {
+        Some(Value::Int(count)) => SampleValue::Sampled(*count as u64),
+        _ => SampleValue::NotFound,
+    }
+}
+
+// Gets stats about a batch of keys in a single round trip, returning a result for each key (in the
+// same order). Element counts take a second round trip, since the command depends on the key's
+// type. The pipelines are NOT atomic; wrapping a whole batch in MULTI/EXEC would block Redis
+// for the entire batch, whereas this lets other clients' commands run in between ours. Callers
+// should keep batches to --batch-size keys so no single pipeline gets too big.
+//
+// An error reply to any command in a pipeline fails the whole pipeline (as does a connection
+// error), so if that happens, the keys are retried one at a time, and only the ones that still fail
+// are skipped.
+pub fn sample_keys(
+    keys: &[Key],
+    config: &Config,
+    conn: &mut Connection,
+) -> Vec<Result<Sample, String>> {
+    match sample_batch(keys, config, conn) {
+        Ok(samples) => samples,
+        Err(e) if keys.len() > 1 => {
+            eprintln!(
+                "Sampling a batch of {} keys failed ({}), retrying them one at a time",
+                keys.len(),
+                e
+            );
+            keys.iter()
+                .map(
+                    |key| match sample_batch(std::slice::from_ref(key), config, conn) {
+                        Ok(mut samples) => samples.remove(0),
+                        Err(e) => Err(e),
+                    },
+                )
+                .collect()
+        }
+        Err(e) => keys.iter().map(|_key| Err(e.clone())).collect(),
+    }
 }
 
-pub fn sample_key(key: &String, config: &Config, conn: &mut Connection) -> Result<Sample, String> {
+fn sample_batch(
+    keys: &[Key],
+    config: &Config,
+    conn: &mut Connection,
+) -> Result<Vec<Result<Sample, String>>, String> {
     use crate::sampling::sample::SampleValue::*;
 
-    // Instantiate an atomic pipeline that we'll use to gets stats about this key
+    if keys.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Run the pipeline, then build a Sample from each key's slice of the replies
+    let data: Vec<Value> = stats_pipeline(keys, config)
+        .query(conn)
+        .map_err(|e| format!("Redis pipeline failed: {}", e))?;
+
+    let mut samples: Vec<Sample> = data
+        .chunks(commands_per_key(config))
+        .map(|key_data| Sample::new(key_data, config))
+        .collect();
+
+    if config.has_stat(&Stats::Count) {
+        add_counts(keys, &mut samples, conn)?;
+    }
+
+    let samples = keys
+        .iter()
+        .zip(samples)
+        .map(|(key, sample)| {
+            // If EXISTS failed or returned 0, return an error. This can happen when a key gets
+            // deleted from Redis between the time we got it from RANDOMKEY/SCAN and now.
+            // The pipeline isn't atomic, so a key can also be deleted right after EXISTS, in which
+            // case the stats after it are NotFound (and left out of the bins' stats).
+            if sample.exists == NotFound || sample.exists == Sampled(false) {
+                Err(format!("Key '{}' not found", String::from_utf8_lossy(key)))
+            } else {
+                Ok(sample)
+            }
+        })
+        .collect();
+
+    Ok(samples)
+}
+
+// Builds the first pipeline of sample_keys. Every command in it looks keys up WITHOUT touching
+// them, so sampling never changes a key's idle time or access frequency (which would throw off
+// LRU/LFU eviction, and the idle/freq stats themselves).
+fn stats_pipeline(keys: &[Key], config: &Config) -> redis::Pipeline {
     let mut pipe = redis::pipe();
-    let mut pipe_ref = pipe.atomic();
 
-    // Add commands to the pipeline for this key, depending on what stats we've requested
-    {
+    // Add commands to the pipeline for each key, depending on what stats we've requested
+    for key in keys {
         // Always check whether this key exists (in case it's since expired)
         // https://redis.io/commands/exists
-        pipe_ref = pipe_ref.cmd("EXISTS").arg(key);
+        pipe.cmd("EXISTS").arg(key);
 
         // Get the memory usage of the key, sampling ALL values if this is a nested data type
         // https://redis.io/commands/memory-usage
         if config.has_stat(&Stats::Memory) {
-            pipe_ref = pipe_ref
-                .cmd("MEMORY")
+            pipe.cmd("MEMORY")
                 .arg("USAGE")
                 .arg(key)
                 .arg("SAMPLES")
                 .arg("0");
         }
 
-        // Get the TTL of the key in seconds
-        // https://redis.io/commands/ttl
+        // Get the TTL of the key in milliseconds. PEXPIRETIME would give us the absolute expiry
+        // time directly, but it needs Redis 7.0+, and we can work that out from the PTTL anyways.
+        // https://redis.io/commands/pttl
         if config.has_stat(&Stats::Ttl) {
-            pipe_ref = pipe_ref.cmd("TTL").arg(key);
+            pipe.cmd("PTTL").arg(key);
         }
 
         // Get the data type of the key
         // https://redis.io/commands/type
-        if config.has_stat(&Stats::Type) {
-            pipe_ref = pipe_ref.cmd("TYPE").arg(key);
+        if collects_type(config) {
+            pipe.cmd("TYPE").arg(key);
+        }
+
+        // Get the internal representation of the key's value, like "listpack" or "hashtable"
+        // https://redis.io/commands/object-encoding
+        if config.has_stat(&Stats::Encoding) {
+            pipe.cmd("OBJECT").arg("ENCODING").arg(key);
+        }
+
+        // Get the number of seconds since the key was last accessed. This is only tracked when the
+        // maxmemory-policy is NOT an LFU one.
+        // https://redis.io/commands/object-idletime
+        if config.has_stat(&Stats::Idle) {
+            pipe.cmd("OBJECT").arg("IDLETIME").arg(key);
+        }
+
+        // Get the key's logarithmic access frequency counter. This is only tracked when the
+        // maxmemory-policy IS an LFU one.
+        // https://redis.io/commands/object-freq
+        if config.has_stat(&Stats::Freq) {
+            pipe.cmd("OBJECT").arg("FREQ").arg(key);
         }
     }
 
-    // Run the pipeline and build the Sample
-    let data: Vec<Value> = pipe_ref
-        .query(conn)
-        .map_err(|e| format!("Redis pipeline failed: {}", e))?;
-    let sample = Sample::new(&data, config);
+    pipe
+}
+
+// Runs a second pipeline to get the element count of each key whose type we know. If a key is
+// deleted in between, its count will be 0 (which is what Redis reports for a missing key), and if
+// it's recreated as a different type, Redis replies with WRONGTYPE, so that key's count is
+// NotFound. The replies are read one at a time so that an error reply only affects its own key
+// (query() would fail the whole pipeline). Unlike the first pipeline, these commands DO touch keys,
+// which is why collect_samples turns on CLIENT NO-TOUCH when counts are requested.
+fn add_counts(keys: &[Key], samples: &mut [Sample], conn: &mut Connection) -> Result<(), String> {
+    let mut pipe = redis::pipe();
+    let mut counted = Vec::new();
+
+    for (idx, (key, sample)) in keys.iter().zip(samples.iter()).enumerate() {
+        let command = sample.type_.get().and_then(|type_| count_command(type_));
+        if let Some(command) = command {
+            pipe.cmd(command).arg(key);
+            counted.push(idx);
+        }
+    }
 
-    // If EXISTS failed or returned 0, return an error. This can happen when a key gets deleted from
-    // Redis between the time we got it from RANDOMKEY and now.
-    if sample.exists == NotFound || sample.exists == Sampled(false) {
-        Err(format!("Key '{}' not found", key))
-    } else {
-        Ok(sample)
+    if counted.is_empty() {
+        return Ok(());
     }
+
+    let failed = |e: redis::RedisError| format!("Redis pipeline failed: {}", e);
+    conn.send_packed_command(&pipe.get_packed_pipeline())
+        .map_err(failed)?;
+
+    for idx in counted {
+        samples[idx].count = match conn.recv_response() {
+            Ok(value) => decode_count(Some(&value)),
+            // Only a broken connection fails the batch; the rest are error replies like WRONGTYPE
+            Err(e) if e.is_io_error() => return Err(failed(e)),
+            Err(_) => SampleValue::NotFound,
+        };
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -168,12 +405,15 @@ mod tests {
     fn sample_key_works_1() {
         let (config, mut conn) = test_config_and_conn();
         let _: () = conn.set_ex("sample_key_works_1", "test_value", 10).unwrap();
-        let sample = sample_key(&"sample_key_works_1".to_string(), &config, &mut conn).unwrap();
+        let keys = vec![b"sample_key_works_1".to_vec()];
+        let sample = sample_keys(&keys, &config, &mut conn).remove(0).unwrap();
 
-        assert_eq!(sample.exists(), true);
+        assert!(sample.exists());
         assert!(sample.memory() > 0);
-        assert_eq!(sample.ttl(), 10);
+        assert!(sample.ttl_ms() > 9_000 && sample.ttl_ms() <= 10_000);
+        assert!(sample.expires_at.get().unwrap() > &SystemTime::now());
         assert_eq!(sample.type_(), "string".to_string());
+        assert_eq!(sample.count(), 10);
     }
 
     #[test]
@@ -182,11 +422,125 @@ mod tests {
         let _: () = conn.sadd("sample_key_works_2", "a").unwrap();
         let _: () = conn.sadd("sample_key_works_2", "b").unwrap();
         let _: () = conn.sadd("sample_key_works_2", "c").unwrap();
-        let sample = sample_key(&"sample_key_works_2".to_string(), &config, &mut conn).unwrap();
+        let keys = vec![b"sample_key_works_2".to_vec()];
+        let sample = sample_keys(&keys, &config, &mut conn).remove(0).unwrap();
 
-        assert_eq!(sample.exists(), true);
+        assert!(sample.exists());
         assert!(sample.memory() > 0);
-        assert_eq!(sample.ttl(), -1);
+        assert_eq!(sample.ttl_ms(), -1);
+        assert_eq!(sample.expires_at, SampleValue::NotFound);
         assert_eq!(sample.type_(), "set".to_string());
+        assert_eq!(sample.count(), 3);
+        assert!(!sample.encoding().is_empty());
+    }
+
+    #[test]
+    fn sample_keys_works() {
+        let (config, mut conn) = test_config_and_conn();
+        let _: () = conn
+            .set_ex("sample_keys_works_1", "test_value", 10)
+            .unwrap();
+        let _: () = conn.del("sample_keys_works_2").unwrap();
+        let _: () = conn.sadd("sample_keys_works_3", "a").unwrap();
+        // Keys don't have to be valid UTF-8
+        let binary_key = b"sample_keys_works_\xff\x00".to_vec();
+        let _: () = conn.set_ex(&binary_key, "test_value", 10).unwrap();
+        let keys = vec![
+            b"sample_keys_works_1".to_vec(),
+            b"sample_keys_works_2".to_vec(),
+            b"sample_keys_works_3".to_vec(),
+            binary_key,
+        ];
+        let samples = sample_keys(&keys, &config, &mut conn);
+
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0].as_ref().unwrap().type_(), "string".to_string());
+        assert!(samples[1].is_err());
+        assert_eq!(samples[2].as_ref().unwrap().type_(), "set".to_string());
+        assert_eq!(samples[3].as_ref().unwrap().type_(), "string".to_string());
+    }
+
+    #[test]
+    fn sample_keys_skips_failed_keys() {
+        let (mut config, mut conn) = test_config_and_conn();
+        let _: () = conn.set("sample_keys_skips_failed_keys", "a").unwrap();
+        let keys = vec![
+            b"sample_keys_skips_failed_keys".to_vec(),
+            b"sample_keys_skips_failed_keys".to_vec(),
+        ];
+
+        // Redis replies with an error for whichever of idle/freq it isn't tracking, which fails the
+        // whole pipeline (and then each key on its own)
+        let policy = crate::metadata::get_maxmemory_policy(&mut conn).unwrap();
+        config.stats = match policy.contains("lfu") {
+            true => vec![Stats::Memory, Stats::Idle],
+            false => vec![Stats::Memory, Stats::Freq],
+        };
+        let samples = sample_keys(&keys, &config, &mut conn);
+        assert_eq!(samples.len(), 2);
+        assert!(samples.iter().all(|s| s.is_err()));
+
+        // The connection is still usable afterwards
+        config.stats = vec![Stats::Memory];
+        let samples = sample_keys(&keys, &config, &mut conn);
+        assert!(samples.iter().all(|s| s.is_ok()));
+    }
+
+    #[test]
+    fn add_counts_skips_wrong_types() {
+        let (config, mut conn) = test_config_and_conn();
+        let _: () = conn.set("add_counts_skips_wrong_types_1", "abc").unwrap();
+        let _: () = conn.set("add_counts_skips_wrong_types_2", "abc").unwrap();
+        let keys = vec![
+            b"add_counts_skips_wrong_types_1".to_vec(),
+            b"add_counts_skips_wrong_types_2".to_vec(),
+        ];
+        // As if the first key was recreated as a string after it was sampled as a list
+        let mut samples = vec![Sample::new(&[], &config), Sample::new(&[], &config)];
+        samples[0].type_ = SampleValue::Sampled("list".to_string());
+        samples[1].type_ = SampleValue::Sampled("string".to_string());
+
+        add_counts(&keys, &mut samples, &mut conn).unwrap();
+        assert_eq!(samples[0].count, SampleValue::NotFound);
+        assert_eq!(samples[1].count, SampleValue::Sampled(3));
+
+        // The connection is still in sync afterwards
+        let value: String = conn.get("add_counts_skips_wrong_types_2").unwrap();
+        assert_eq!(value, "abc");
+    }
+
+    #[test]
+    fn sample_keys_never_touches_keys() {
+        let (config, mut conn) = test_config_and_conn();
+        assert!(config.has_stat(&Stats::Count));
+        let key = "sample_keys_never_touches_keys";
+        let _: () = conn.set(key, "a").unwrap();
+        crate::sampling::no_touch(&mut conn).unwrap();
+
+        // Redis tracks either idle time or access frequency, depending on the maxmemory-policy
+        let access = |conn: &mut Connection| -> i64 {
+            let subcommand = match config.has_stat(&Stats::Idle) {
+                true => "IDLETIME",
+                false => "FREQ",
+            };
+            redis::cmd("OBJECT")
+                .arg(subcommand)
+                .arg(key)
+                .query(conn)
+                .unwrap()
+        };
+        std::thread::sleep(Duration::from_millis(2_100));
+        let before = access(&mut conn);
+
+        let keys = vec![key.as_bytes().to_vec()];
+        let sample = sample_keys(&keys, &config, &mut conn).remove(0).unwrap();
+        assert_eq!(sample.count(), 1);
+
+        let after = access(&mut conn);
+        match config.has_stat(&Stats::Idle) {
+            true => assert!(before >= 2 && after >= before, "{} -> {}", before, after),
+            // The access frequency only ever decays when keys aren't touched
+            false => assert!(after <= before, "{} -> {}", before, after),
+        }
     }
 }
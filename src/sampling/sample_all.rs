@@ -1,41 +1,148 @@
-use std::thread::sleep;
-use std::time::Duration;
-
 use redis::Connection;
 
 use crate::config::Config;
+use crate::data::pattern::{glob_matches, Glob};
 use crate::data::*;
-use crate::sampling::sample::sample_key;
-
-pub fn sample_all(config: &Config, conn: &mut Connection) -> Data {
-    let mut data = Data::new(config);
-
-    let scan_keys: Vec<String> = redis::cmd("SCAN")
-        .cursor_arg(0)
-        .arg("COUNT")
-        .arg(config.batch_size)
-        .clone()
-        .iter(conn)
-        .unwrap()
-        .collect();
-
-    // This counter is used to sleep after each batch of n_samples
-    let mut batch_count = 0;
-
-    for key in scan_keys {
-        if !data.has_sample(&key) {
-            let sample = sample_key(&key, config, conn);
-            if let Ok(sample) = sample {
-                data.add_sample(key, sample);
+use crate::metadata::{MatchCounts, Metadata};
+use crate::sampling::sample::sample_keys;
+use crate::sampling::throttle::Throttle;
+
+// Enumerates keys with SCAN. If any --match patterns were given, they're pushed down into SCAN's
+// MATCH option so Redis does the filtering. Since MATCH only accepts a single pattern, each pattern
+// gets its own cursor; this means the keyspace is walked once per pattern, but only matching keys
+// are sent back to us and sampled. Keys matching an --exclude pattern are still sent back (MATCH
+// can't exclude anything), but are dropped before they're sampled.
+//
+// Each page of keys is sampled before the next one is requested, and samples are folded into their
+// bin's stats as they arrive (and streamed to --raw, if given), so memory use stays flat no matter
+// how many keys there are. Stats for a batch of keys are collected with a single pipeline, and we
+// sleep after each one (see throttle.rs for how batches are sized).
+//
+// Since sampled keys aren't remembered, a key that matches more than one --match pattern is only
+// sampled by the cursor for the first of them. SCAN itself can (rarely) return a key more than once
+// if the keyspace is resized mid-scan, and those duplicates are counted twice.
+pub fn sample_all(
+    config: &Config,
+    conn: &mut Connection,
+    metadata: &mut Metadata,
+    data: &mut Data,
+) {
+    let mut throttle = Throttle::new(config);
+
+    // Number of keys SCAN returned to us, and how many of them were not excluded or already sampled
+    // by a previous cursor
+    let mut examined = 0;
+    let mut matched = 0;
+
+    let match_patterns = scan_match_patterns(config);
+    for (idx, match_pattern) in match_patterns.iter().enumerate() {
+        let earlier_patterns = &match_patterns[..idx];
+        let mut cursor = 0;
+
+        loop {
+            let count = throttle.batch_size();
+            let (next_cursor, scan_keys) =
+                throttle.time(|| scan_page(cursor, *match_pattern, count, conn));
+
+            // SCAN can return the same key more than once, and COUNT is only a hint, so dedupe
+            // this page (skipping keys an earlier cursor already sampled, and excluded keys) and
+            // split it into batches
+            examined += scan_keys.len() as u64;
+            let mut new_keys: Vec<Key> = Vec::with_capacity(scan_keys.len());
+            for key in scan_keys {
+                let sampled_earlier = earlier_patterns
+                    .iter()
+                    .flatten()
+                    .any(|p| glob_matches(p, &key));
+                if !sampled_earlier && config.is_match(&key) && !new_keys.contains(&key) {
+                    new_keys.push(key);
+                }
             }
+            matched += new_keys.len() as u64;
+
+            while !new_keys.is_empty() {
+                let batch_size = throttle.batch_size().min(new_keys.len());
+                let batch: Vec<Key> = new_keys.drain(..batch_size).collect();
+                let samples = throttle.time(|| sample_keys(&batch, config, conn));
+                for (key, sample) in batch.into_iter().zip(samples) {
+                    if let Ok(sample) = sample {
+                        data.add_sample(key, sample);
+                    }
+                }
 
-            batch_count += 1;
-            if batch_count == config.n_samples {
-                sleep(Duration::from_millis(config.batch_sleep_ms));
-                batch_count = 0;
+                throttle.end_batch(conn);
             }
+
+            // A cursor of 0 means the iteration is complete
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
         }
     }
 
-    data
+    // Redis doesn't tell us how many keys it looked at to produce a page of MATCH results (and keys
+    // can come and go mid-scan), so only the number of matching keys is known. Without MATCH (only
+    // --exclude, or a "*" pattern), every key SCAN looked at was returned to us.
+    if config.filters_keys() {
+        let unfiltered = match_patterns.iter().all(|p| p.is_none());
+        metadata.match_counts = Some(MatchCounts {
+            examined: unfiltered.then_some(examined),
+            matched,
+        });
+    }
+}
+
+// Returns the MATCH pattern to use for each SCAN cursor, where None means "don't filter". A "*"
+// pattern matches everything, so if one was given, a single unfiltered cursor is all we need.
+fn scan_match_patterns(config: &Config) -> Vec<Option<&Glob>> {
+    let patterns = &config.match_patterns;
+
+    if patterns.is_empty() || patterns.iter().any(|p| p.as_str() == "*") {
+        vec![None]
+    } else {
+        patterns.iter().map(Some).collect()
+    }
+}
+
+// Runs a single SCAN command, returning the next cursor and this page of keys
+fn scan_page(
+    cursor: u64,
+    match_pattern: Option<&Glob>,
+    count: usize,
+    conn: &mut Connection,
+) -> (u64, Vec<Key>) {
+    let mut cmd = redis::cmd("SCAN");
+    cmd.arg(cursor);
+
+    // https://redis.io/commands/scan#the-match-option
+    if let Some(pattern) = match_pattern {
+        cmd.arg("MATCH").arg(pattern.as_str());
+    }
+
+    cmd.arg("COUNT").arg(count);
+    cmd.query(conn).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_config_and_conn;
+
+    #[test]
+    fn scan_match_patterns_works() {
+        let (mut config, _conn) = test_config_and_conn();
+        assert_eq!(scan_match_patterns(&config), vec![None]);
+
+        let user = Glob::new("user:*");
+        let company = Glob::new("company:*");
+        config.match_patterns = vec![user.clone(), company.clone()];
+        assert_eq!(
+            scan_match_patterns(&config),
+            vec![Some(&user), Some(&company)]
+        );
+
+        config.match_patterns.push(Glob::new("*"));
+        assert_eq!(scan_match_patterns(&config), vec![None]);
+    }
 }
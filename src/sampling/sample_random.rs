@@ -1,97 +1,212 @@
 use std::borrow::BorrowMut;
-use std::thread::sleep;
-use std::time::Duration;
 
 use redis::{Connection, RedisResult};
 
 use crate::config::Config;
 use crate::data::*;
-use crate::sampling::sample::sample_key;
+use crate::metadata::{MatchCounts, Metadata};
+use crate::sampling::sample::{sample_keys, Sample};
+use crate::sampling::script::SampleScript;
+use crate::sampling::throttle::Throttle;
+use crate::sampling::SampleBackend;
+use crate::stats::Stats;
 
-// This could be more efficient by pipelining more commands. Right now, the order of operations
-// looks like this:
+// Each batch takes two round trips to Redis, no matter how big it is:
 //
 //     keys = PIPELINE(["RANDOMKEY", "RANDOMKEY", ..., "RANDOMKEY"])
-//     for key in keys {
-//         stats = PIPELINE(["EXISTS $key", "MEMORY USAGE $key", "TTL $key", ...])
-//     }
+//     stats = PIPELINE(["EXISTS $key1", "MEMORY USAGE $key1", ..., "EXISTS $key2", ...])
 //
-// That's fine, but we could get more aggressive by doing something like pipelining the stats for
-// multiple keys at the same, time, or even using Lua to get random keys AND their stats in one
-// operation.
+// Neither pipeline is atomic, and both are bounded by --batch-size, so we won't risk blocking Redis
+// with a massive command.
 //
-// For now, we'll keep this kind of slow implementation that uses N+1 pipelines for N keys. At least
-// we won't risk blocking Redis with a massive pipelined command.
+// With --backend=lua, a script does both steps server-side, so each batch is a single round trip
+// (see script.rs). If scripting is disabled or denied by an ACL, we fall back to the pipelines.
 //
 // Unrelated note: if we don't find found any new keys for 10 batches in a row, this function will
 // exit before n_samples has been collected. This guards against sampling indefinitely if Redis has
 // fewer than n_samples keys total.
-pub fn sample_random(config: &Config, conn: &mut Connection) -> Data {
-    let mut data = Data::new(config);
+//
+// When --match patterns are given, this does rejection sampling: random keys that don't match are
+// thrown away, which keeps the sample uniform over the matching keys. The fraction of random keys
+// that matched is recorded in the Metadata so the size of the matching population can be
+// estimated. Since a rare pattern may need a LOT of random keys, at most --max-draws are tried.
+//
+// With --target-error, n_samples is only the minimum: sampling keeps going until every bin's
+// estimated key count (and total memory, if collected) is within that percentage of the true value
+// at 95% confidence, or until --max-draws random keys have been tried.
+pub fn sample_random(
+    config: &Config,
+    conn: &mut Connection,
+    metadata: &mut Metadata,
+    data: &mut Data,
+) {
+    let mut script = match config.sample_backend {
+        SampleBackend::Pipeline => None,
+        SampleBackend::Lua => load_script(conn),
+    };
+    let mut throttle = Throttle::new(config);
     let mut no_new_keys_streak = 0;
+    // Number of random keys drawn, and how many of them matched
+    let mut draws = 0;
+    let mut matched = 0;
 
     loop {
-        // Get a batch of random keys
-        let batch_size = this_batch_size(config, &data);
-        let keys = get_random_keys(batch_size, conn).unwrap();
-
-        // Initialized as true, but set to false if any new keys are sampled this batch
-        let mut no_new_keys = true;
-
-        // Sample each key, and add it to our Data struct if successful. Skip keys that have already
-        // been sampled.
-        for key in keys {
-            if !data.has_sample(&key) {
-                let sample = sample_key(&key, config, conn);
-                if let Ok(sample) = sample {
-                    data.add_sample(key, sample);
-                    no_new_keys = false;
-                    no_new_keys_streak = 0;
+        // Get a batch of random keys, then drop any that don't match
+        let batch_size = this_batch_size(config, throttle.batch_size(), data, draws);
+        let keys = match &script {
+            Some(s) => match throttle.time(|| s.random_samples(batch_size, config, conn)) {
+                Ok(samples) => samples.into_iter().map(|(k, s)| (k, Some(s))).collect(),
+                Err(e) => {
+                    eprintln!("Lua sampling failed ({}), falling back to pipelines", e);
+                    script = None;
+                    continue;
                 }
+            },
+            None => {
+                let keys = throttle.time(|| get_random_keys(batch_size, conn)).unwrap();
+                keys.into_iter().map(|k| (k, None)).collect::<Vec<_>>()
+            }
+        };
+        draws += keys.len() as u64;
+        let keys: Vec<(Key, Option<Sample>)> = keys
+            .into_iter()
+            .filter(|(k, _)| config.is_match(k))
+            .collect();
+        matched += keys.len() as u64;
+        if config.filters_keys() {
+            metadata.match_counts = Some(MatchCounts {
+                examined: Some(draws),
+                matched,
+            });
+        }
+
+        // Initialized as true, but set to false if any new keys are sampled this batch. A batch
+        // where nothing matched doesn't count towards the streak; that's what --max-draws is for.
+        let mut no_new_keys = !keys.is_empty();
+
+        // Skip keys that have already been sampled (or that showed up twice in this batch), and
+        // only keep as many as we still need (full batches can overshoot when rejection sampling).
+        // With --target-error, there's no telling how many we need, so keep them all.
+        let mut new_keys: Vec<Key> = Vec::with_capacity(keys.len());
+        let mut script_samples = Vec::with_capacity(keys.len());
+        for (key, sample) in keys {
+            if !data.has_sample(&key) && !new_keys.contains(&key) {
+                new_keys.push(key);
+                script_samples.push(sample);
+            }
+        }
+        if config.target_error_pct.is_none() {
+            new_keys.truncate(config.n_samples.saturating_sub(data.sample_count()));
+        }
+        script_samples.truncate(new_keys.len());
+
+        // Sample the whole batch at once (unless the script already did), and add each key to our
+        // Data struct if successful
+        let samples: Vec<Result<Sample, String>> =
+            match script_samples.into_iter().collect::<Option<Vec<_>>>() {
+                Some(samples) => samples.into_iter().map(Ok).collect(),
+                None => throttle.time(|| sample_keys(&new_keys, config, conn)),
+            };
+        for (key, sample) in new_keys.into_iter().zip(samples) {
+            if let Ok(sample) = sample {
+                data.add_sample(key, sample);
+                no_new_keys = false;
+                no_new_keys_streak = 0;
             }
         }
 
+        // Continue sampling until we've surpassed `n_samples` (and met --target-error, if given)
+        let done = data.sample_count() >= config.n_samples
+            && match config.target_error_pct {
+                Some(target_pct) => meets_target_error(config, metadata, data, target_pct),
+                None => true,
+            };
+        if done {
+            break;
+        }
+
         // Increment, then check our current streak of not finding new keys
         if no_new_keys {
             no_new_keys_streak += 1;
         }
-        if no_new_keys_streak == 10 {
-            eprintln!(
-                "Could only reasonably sample {} keys (of {} requested)",
-                data.sample_count(),
-                config.n_samples,
-            );
+        let limits_draws = config.filters_keys() || config.target_error_pct.is_some();
+        if no_new_keys_streak == 10 || (limits_draws && draws >= config.max_draws as u64) {
+            if data.sample_count() < config.n_samples {
+                eprintln!(
+                    "Could only reasonably sample {} keys (of {} requested)",
+                    data.sample_count(),
+                    config.n_samples,
+                );
+            } else {
+                eprintln!(
+                    "Stopped after sampling {} keys, before every estimate was within {}%",
+                    data.sample_count(),
+                    config.target_error_pct.unwrap(),
+                );
+            }
             break;
         }
 
-        // Continue sampling until we've surpassed `n_samples`, sleeping after each batch so we
-        // don't hammer Redis too hard.
-        if data.sample_count() < config.n_samples {
-            sleep(Duration::from_millis(config.batch_sleep_ms));
-        } else {
-            break;
-        }
+        // Sleep after each batch so we don't hammer Redis too hard
+        throttle.end_batch(conn);
     }
+}
+
+// Whether every bin's estimated key count (and total memory, if collected) is within `target_pct`
+// percent of the true value, at 95% confidence. Bins without any sampled keys are skipped, since
+// there's nothing to estimate the error of.
+fn meets_target_error(config: &Config, metadata: &Metadata, data: &Data, target_pct: u64) -> bool {
+    let is_within_target = |estimate: u64, margin: Option<u64>| match margin {
+        Some(margin) => margin as f64 <= estimate as f64 * target_pct as f64 / 100_f64,
+        None => false,
+    };
 
-    data
+    data.bins()
+        .map(|(_bin_key, bin)| bin)
+        .filter(|bin| bin.key_count > 0)
+        .all(|bin| {
+            is_within_target(
+                keys::total_estimate(metadata, data, bin),
+                keys::total_estimate_margin(metadata, data, bin),
+            ) && (!config.has_stat(&Stats::Memory)
+                || is_within_target(
+                    memory::total_estimate(metadata, data, bin),
+                    memory::total_estimate_margin(metadata, data, bin),
+                ))
+        })
 }
 
-// By default, use the batch size in the Config object. If we have just a few samples left to
-// collect, simply collect that number directly.
-fn this_batch_size(config: &Config, data: &Data) -> usize {
+// By default, use the batch size from the Throttle. If we have just a few samples left to collect,
+// simply collect that number directly. When rejection sampling or sampling to --target-error, we
+// can't know how many more keys we'll need, so we always take full batches (but never go past
+// --max-draws).
+fn this_batch_size(config: &Config, default_batch_size: usize, data: &Data, draws: u64) -> usize {
     let n_samples = config.n_samples;
-    let default_batch_size = config.batch_size;
 
-    if data.sample_count() + default_batch_size > n_samples {
+    if config.filters_keys() || config.target_error_pct.is_some() {
+        let draws_left = (config.max_draws as u64).saturating_sub(draws);
+        default_batch_size.min(draws_left as usize)
+    } else if data.sample_count() + default_batch_size > n_samples {
         n_samples - data.sample_count()
     } else {
         default_batch_size
     }
 }
 
+// Loads the sampling script, or returns None (with a warning) if Redis won't let us use it
+fn load_script(conn: &mut Connection) -> Option<SampleScript> {
+    match SampleScript::load(conn) {
+        Ok(script) => Some(script),
+        Err(e) => {
+            eprintln!("Can't use Lua sampling ({}), falling back to pipelines", e);
+            None
+        }
+    }
+}
+
 // This uses a single pipelined command of multiple "RANDOMKEY" commands to get the requested number
 // of random keys. Note that duplicate keys might be returned by this function.
-fn get_random_keys(n_keys: usize, conn: &mut Connection) -> RedisResult<Vec<String>> {
+fn get_random_keys(n_keys: usize, conn: &mut Connection) -> RedisResult<Vec<Key>> {
     if n_keys == 0 {
         Ok(Vec::new())
     } else {
@@ -103,3 +218,30 @@ fn get_random_keys(n_keys: usize, conn: &mut Connection) -> RedisResult<Vec<Stri
         pipe_ref.query(conn)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::pattern::Glob;
+    use crate::tests::test_config_and_conn;
+
+    #[test]
+    fn this_batch_size_works() {
+        let (mut config, _conn) = test_config_and_conn();
+        config.n_samples = 10;
+        config.max_draws = 10;
+        let data = Data::new(&config);
+        assert_eq!(this_batch_size(&config, 4, &data, 0), 4);
+
+        config.match_patterns = vec![Glob::new("user:*")];
+        assert_eq!(this_batch_size(&config, 4, &data, 0), 4);
+        assert_eq!(this_batch_size(&config, 4, &data, 8), 2);
+        assert_eq!(this_batch_size(&config, 4, &data, 12), 0);
+
+        config.match_patterns = vec![];
+        config.n_samples = 2;
+        assert_eq!(this_batch_size(&config, 4, &data, 12), 2);
+        config.target_error_pct = Some(5);
+        assert_eq!(this_batch_size(&config, 4, &data, 0), 4);
+    }
+}
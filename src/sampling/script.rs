@@ -0,0 +1,204 @@
+use redis::{Connection, ErrorKind, Value};
+
+use crate::config::Config;
+use crate::data::Key;
+use crate::sampling::sample::{collects_type, commands_per_key, decode_count, Sample};
+use crate::stats::Stats;
+
+// The maximum number of keys a single call of the script will sample. Scripts block Redis until
+// they finish, so this keeps each call down to a few dozen commands that are each O(1) (or bounded
+// by MEMORY_SAMPLES). Larger batches are split into several calls, which are then pipelined.
+const MAX_KEYS_PER_CALL: usize = 16;
+
+// How many elements of a collection MEMORY USAGE looks at to estimate its size, in the script. The
+// pipelines use "SAMPLES 0" to look at every element, which is O(N), and is fine there since other
+// clients' commands can run in between ours; in a script, 16 big hashes would block Redis for as
+// long as it takes to walk all of them. 5 is Redis' own default.
+const MEMORY_SAMPLES: usize = 5;
+
+// Gets random keys along with their stats, entirely server-side. The replies are laid out exactly
+// like the first pipeline in sample_keys (preceded by the key itself, and followed by its element
+// count if requested), so that Sample::new can decode them. Since the script runs atomically,
+// every key is guaranteed to exist.
+//
+//     ARGV[1]: number of keys to sample
+//     ARGV[2..8]: "1" to collect memory, TTL, type, encoding, idle time, frequency, and count
+//                 respectively, or "0" to skip it
+//     ARGV[9]: the SAMPLES option for MEMORY USAGE
+const SCRIPT: &str = r#"
+local count_commands = {
+    string = "STRLEN", list = "LLEN", set = "SCARD", zset = "ZCARD", hash = "HLEN", stream = "XLEN"
+}
+
+local result = {}
+for _ = 1, tonumber(ARGV[1]) do
+    local key = redis.call("RANDOMKEY")
+    if not key then
+        break
+    end
+
+    table.insert(result, key)
+    table.insert(result, 1)
+    if ARGV[2] == "1" then
+        table.insert(result, redis.call("MEMORY", "USAGE", key, "SAMPLES", ARGV[9]))
+    end
+    if ARGV[3] == "1" then
+        table.insert(result, redis.call("PTTL", key))
+    end
+    local key_type
+    if ARGV[4] == "1" then
+        key_type = redis.call("TYPE", key)["ok"]
+        table.insert(result, {ok = key_type})
+    end
+    if ARGV[5] == "1" then
+        table.insert(result, redis.call("OBJECT", "ENCODING", key))
+    end
+    if ARGV[6] == "1" then
+        table.insert(result, redis.call("OBJECT", "IDLETIME", key))
+    end
+    if ARGV[7] == "1" then
+        table.insert(result, redis.call("OBJECT", "FREQ", key))
+    end
+    if ARGV[8] == "1" then
+        local count_command = count_commands[key_type]
+        table.insert(result, count_command and redis.call(count_command, key) or false)
+    end
+end
+return result
+"#;
+
+pub struct SampleScript {
+    sha: String,
+}
+
+impl SampleScript {
+    // Loads the script into Redis with SCRIPT LOAD. This fails if scripting is disabled or denied
+    // by an ACL, in which case the caller should fall back to sampling with plain pipelines.
+    pub fn load(conn: &mut Connection) -> Result<SampleScript, String> {
+        // https://redis.io/commands/script-load
+        let sha: String = redis::cmd("SCRIPT")
+            .arg("LOAD")
+            .arg(SCRIPT)
+            .query(conn)
+            .map_err(|e| e.to_string())?;
+
+        Ok(SampleScript { sha })
+    }
+
+    // Gets `n_keys` random keys and their stats with a single pipeline of EVALSHA calls. Like
+    // RANDOMKEY, this can return duplicate keys. If the script was flushed from Redis since it was
+    // loaded (e.g. by SCRIPT FLUSH or a failover), it's loaded again and the pipeline is retried.
+    pub fn random_samples(
+        &self,
+        n_keys: usize,
+        config: &Config,
+        conn: &mut Connection,
+    ) -> Result<Vec<(Key, Sample)>, String> {
+        if n_keys == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut pipe = redis::pipe();
+        let mut keys_left = n_keys;
+        while keys_left > 0 {
+            let keys_this_call = keys_left.min(MAX_KEYS_PER_CALL);
+            keys_left -= keys_this_call;
+
+            // https://redis.io/commands/evalsha
+            pipe.cmd("EVALSHA")
+                .arg(&self.sha)
+                .arg(0)
+                .arg(keys_this_call)
+                .arg(stat_flag(config, Stats::Memory))
+                .arg(stat_flag(config, Stats::Ttl))
+                .arg(if collects_type(config) { "1" } else { "0" })
+                .arg(stat_flag(config, Stats::Encoding))
+                .arg(stat_flag(config, Stats::Idle))
+                .arg(stat_flag(config, Stats::Freq))
+                .arg(stat_flag(config, Stats::Count))
+                .arg(MEMORY_SAMPLES);
+        }
+
+        let replies: Vec<Value> = match pipe.query(conn) {
+            Err(e) if e.kind() == ErrorKind::NoScriptError => {
+                SampleScript::load(conn)?;
+                pipe.query(conn).map_err(|e| e.to_string())?
+            }
+            result => result.map_err(|e| e.to_string())?,
+        };
+
+        let mut samples = Vec::with_capacity(n_keys);
+        for reply in replies {
+            samples.extend(decode_reply(reply, config)?);
+        }
+
+        Ok(samples)
+    }
+}
+
+fn stat_flag(config: &Config, stat: Stats) -> &'static str {
+    if config.has_stat(&stat) {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+// Splits the flat array returned by one call of the script into each key and its Sample
+fn decode_reply(reply: Value, config: &Config) -> Result<Vec<(Key, Sample)>, String> {
+    let values = match reply {
+        Value::Bulk(values) => values,
+        other => {
+            return Err(format!(
+                "Unexpected reply from sampling script: {:?}",
+                other
+            ))
+        }
+    };
+
+    let has_count = config.has_stat(&Stats::Count);
+    values
+        .chunks(1 + commands_per_key(config) + has_count as usize)
+        .map(|chunk| {
+            let key = redis::from_redis_value(&chunk[0]).map_err(|e| e.to_string())?;
+            let mut sample = Sample::new(&chunk[1..], config);
+            if has_count {
+                sample.count = decode_count(chunk.get(1 + commands_per_key(config)));
+            }
+            Ok((key, sample))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::sample::SampleValue::*;
+    use crate::tests::test_config_and_conn;
+
+    #[test]
+    fn decode_reply_works() {
+        let (mut config, _conn) = test_config_and_conn();
+        config.stats = vec![Stats::Memory, Stats::Type];
+        let reply = Value::Bulk(vec![
+            Value::Data(b"user:1".to_vec()),
+            Value::Int(1),
+            Value::Int(72),
+            Value::Status("hash".to_string()),
+            Value::Data(b"user:2".to_vec()),
+            Value::Int(1),
+            Value::Int(56),
+            Value::Status("string".to_string()),
+        ]);
+
+        let samples = decode_reply(reply, &config).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].0, b"user:1");
+        assert_eq!(samples[0].1.memory(), 72);
+        assert_eq!(samples[0].1.ttl_ms, Unsampled);
+        assert_eq!(samples[1].0, b"user:2");
+        assert_eq!(samples[1].1.type_(), "string");
+
+        assert!(decode_reply(Value::Nil, &config).is_err());
+    }
+}
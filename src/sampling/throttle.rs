@@ -0,0 +1,195 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use redis::{Connection, InfoDict};
+
+use crate::config::Config;
+
+// The server's load is only polled this often; instantaneous_ops_per_sec is itself only updated
+// every 100ms or so, and polling on every batch would add a round trip to each one.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// In gentle mode, batches start out this small and grow while Redis keeps up
+const GENTLE_START_BATCH_SIZE: usize = 10;
+
+// Sleeping backs off exponentially while Redis is over a target, but never for longer than this
+const MAX_SLEEP: Duration = Duration::from_secs(10);
+
+// Controls how many keys go into each batch and how long to sleep between them. By default, that's
+// just the fixed --batch-size and --batch-sleep-ms. If any of --max-latency-ms, --max-server-ops,
+// or --max-server-cpu are given, this runs in "gentle" mode instead: round trips are timed, the
+// server's load is polled with INFO, and after each batch the batch size and sleep are adjusted to
+// stay under those targets. Like TCP congestion control, batches grow a little at a time while
+// everything is under target, and are cut in half (with the sleep doubled) as soon as anything goes
+// over. In gentle mode, --batch-size is the largest batch that will be used, and --batch-sleep-ms
+// is the shortest sleep.
+pub struct Throttle {
+    batch_size: usize,
+    sleep: Duration,
+    max_batch_size: usize,
+    min_sleep: Duration,
+    max_latency: Option<Duration>,
+    max_server_ops: Option<u64>,
+    max_server_cpu_pct: Option<u64>,
+
+    // The slowest round trip seen during the current batch
+    batch_latency: Duration,
+    // When INFO was last polled, and the total CPU seconds Redis had used by then
+    last_poll: Option<(Instant, f64)>,
+}
+
+impl Throttle {
+    pub fn new(config: &Config) -> Throttle {
+        let mut throttle = Throttle {
+            batch_size: config.batch_size.max(1),
+            sleep: Duration::from_millis(config.batch_sleep_ms),
+            max_batch_size: config.batch_size.max(1),
+            min_sleep: Duration::from_millis(config.batch_sleep_ms),
+            max_latency: config.max_latency_ms.map(Duration::from_millis),
+            max_server_ops: config.max_server_ops,
+            max_server_cpu_pct: config.max_server_cpu_pct,
+            batch_latency: Duration::ZERO,
+            last_poll: None,
+        };
+
+        if throttle.is_gentle() {
+            throttle.batch_size = throttle.batch_size.min(GENTLE_START_BATCH_SIZE);
+        }
+
+        throttle
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    // Runs a single round trip to Redis, keeping track of how long it took
+    pub fn time<T>(&mut self, round_trip: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = round_trip();
+        self.batch_latency = self.batch_latency.max(start.elapsed());
+        result
+    }
+
+    // Called after each batch: adjusts the batch size and sleep (in gentle mode), then sleeps so we
+    // don't hammer Redis too hard.
+    pub fn end_batch(&mut self, conn: &mut Connection) {
+        if self.is_gentle() {
+            let over_target = self.is_over_target(conn);
+            self.adjust(over_target);
+        }
+
+        sleep(self.sleep);
+    }
+
+    fn is_gentle(&self) -> bool {
+        self.max_latency.is_some()
+            || self.max_server_ops.is_some()
+            || self.max_server_cpu_pct.is_some()
+    }
+
+    fn is_over_target(&mut self, conn: &mut Connection) -> bool {
+        let latency = std::mem::take(&mut self.batch_latency);
+        if matches!(self.max_latency, Some(max) if latency > max) {
+            return true;
+        }
+
+        if self.max_server_ops.is_none() && self.max_server_cpu_pct.is_none() {
+            return false;
+        }
+        if matches!(self.last_poll, Some((polled_at, _)) if polled_at.elapsed() < POLL_INTERVAL) {
+            return false;
+        }
+
+        // See the "stats" and "cpu" sections within: https://redis.io/commands/info. Both are part
+        // of the default sections, and older versions of Redis only accept one section name. If
+        // INFO fails for some reason, play it safe and treat that as being over target.
+        let info: InfoDict = match redis::cmd("INFO").query(conn) {
+            Ok(info) => info,
+            Err(_) => return true,
+        };
+        let now = Instant::now();
+        let mut over_target = false;
+
+        // Note that this includes the commands we're sending ourselves
+        if let Some(max_ops) = self.max_server_ops {
+            let ops: u64 = info.get("instantaneous_ops_per_sec").unwrap_or(0);
+            over_target |= ops > max_ops;
+        }
+
+        // The CPU usage since the last poll, as a percentage of a single core
+        let cpu_secs = info.get::<f64>("used_cpu_sys").unwrap_or(0.0)
+            + info.get::<f64>("used_cpu_user").unwrap_or(0.0);
+        if let (Some(max_pct), Some((polled_at, last_cpu_secs))) =
+            (self.max_server_cpu_pct, self.last_poll)
+        {
+            let elapsed = now.duration_since(polled_at).as_secs_f64();
+            let cpu_pct = 100.0 * (cpu_secs - last_cpu_secs) / elapsed;
+            over_target |= cpu_pct > max_pct as f64;
+        }
+        self.last_poll = Some((now, cpu_secs));
+
+        over_target
+    }
+
+    // Additive increase, multiplicative decrease
+    fn adjust(&mut self, over_target: bool) {
+        if over_target {
+            self.batch_size = (self.batch_size / 2).max(1);
+            self.sleep = (self.sleep * 2)
+                .max(Duration::from_millis(10))
+                .min(MAX_SLEEP);
+        } else {
+            self.batch_size =
+                (self.batch_size + (self.batch_size / 4).max(1)).min(self.max_batch_size);
+            // Don't bother decaying a sleep that's already shorter than a millisecond
+            let sleep = self.sleep * 3 / 4;
+            self.sleep = if sleep < Duration::from_millis(1) {
+                self.min_sleep
+            } else {
+                sleep.max(self.min_sleep)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_config_and_conn;
+
+    #[test]
+    fn adjust_works() {
+        let (mut config, _conn) = test_config_and_conn();
+        config.batch_size = 100;
+        config.batch_sleep_ms = 0;
+        assert_eq!(Throttle::new(&config).batch_size(), 100);
+
+        config.max_latency_ms = Some(2);
+        let mut throttle = Throttle::new(&config);
+        assert_eq!(throttle.batch_size(), 10);
+
+        throttle.adjust(false);
+        assert_eq!(throttle.batch_size(), 12);
+        assert_eq!(throttle.sleep, Duration::ZERO);
+
+        throttle.adjust(true);
+        assert_eq!(throttle.batch_size(), 6);
+        assert_eq!(throttle.sleep, Duration::from_millis(10));
+        throttle.adjust(true);
+        assert_eq!(throttle.batch_size(), 3);
+        assert_eq!(throttle.sleep, Duration::from_millis(20));
+
+        for _ in 0..100 {
+            throttle.adjust(false);
+        }
+        assert_eq!(throttle.batch_size(), 100);
+        assert_eq!(throttle.sleep, Duration::ZERO);
+
+        for _ in 0..100 {
+            throttle.adjust(true);
+        }
+        assert_eq!(throttle.batch_size(), 1);
+        assert_eq!(throttle.sleep, MAX_SLEEP);
+    }
+}
@@ -4,8 +4,8 @@ pub(crate) fn seed_fake_data(
 ) -> Result<(), redis::RedisError> {
     use rand::random;
 
-    let fake_resources = vec!["user", "company"];
-    let fake_attributes = vec!["friends", "messages", "memes"];
+    let fake_resources = ["user", "company"];
+    let fake_attributes = ["friends", "messages", "memes"];
 
     let mut pipe = redis::pipe();
     let mut pipe_ref = pipe.atomic();
@@ -25,6 +25,6 @@ pub(crate) fn seed_fake_data(
         }
     }
 
-    pipe_ref.query(conn)?;
+    pipe_ref.query::<()>(conn)?;
     Ok(())
 }
@@ -5,11 +5,15 @@ pub enum Stats {
     Memory,
     Ttl,
     Type,
+    Count,
+    Encoding,
+    Idle,
+    Freq,
 }
 
 impl Stats {
     #[allow(dead_code)]
     pub fn all() -> Vec<Stats> {
-        vec![Memory, Ttl, Type]
+        vec![Memory, Ttl, Type, Count, Encoding, Idle, Freq]
     }
 }